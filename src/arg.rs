@@ -0,0 +1,105 @@
+use {
+    crate::{Error, Host},
+    derive_more::{From, Into},
+    std::{fs, path::Path, str::FromStr, time::Duration},
+};
+
+/// How many echo requests to send: a fixed count, or `forever` until interrupted.
+#[derive(Clone, Copy, Debug, From)]
+pub enum RequestsToSend {
+    Count(u64),
+    Continuous,
+}
+
+impl RequestsToSend {
+    pub(crate) fn exhausted_by(self, sent: u64) -> bool {
+        match self {
+            Self::Count(count) => sent >= count,
+            Self::Continuous => false,
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a str> for RequestsToSend {
+    type Error = Error;
+    fn try_from(text: &'a str) -> Result<Self, Self::Error> {
+        match text {
+            "forever" => Ok(Self::Continuous),
+            text => match u64::from_str(text)? {
+                0 => Err("at least one ping must be requested".to_string().into()),
+                x => Ok(x.into()),
+            },
+        }
+    }
+}
+
+/// Milliseconds to wait before each echo request.
+#[derive(Clone, Copy, Debug, From, Into)]
+pub struct TransmissionInterval(u32);
+
+impl From<TransmissionInterval> for Duration {
+    fn from(interval: TransmissionInterval) -> Self {
+        Duration::from_millis(interval.0.into())
+    }
+}
+
+impl<'a> TryFrom<&'a str> for TransmissionInterval {
+    type Error = Error;
+    fn try_from(text: &'a str) -> Result<Self, Self::Error> {
+        match u32::from_str(text)? {
+            0 => Err("zero interval is not supported".to_string().into()),
+            x => Ok(x.into()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Arg {
+    pub(crate) destination: Host,
+    pub(crate) requests: RequestsToSend,
+    pub(crate) interval: TransmissionInterval,
+}
+
+impl From<(Host, RequestsToSend, TransmissionInterval)> for Arg {
+    fn from(
+        (destination, requests, interval): (Host, RequestsToSend, TransmissionInterval),
+    ) -> Self {
+        Self {
+            destination,
+            requests,
+            interval,
+        }
+    }
+}
+
+impl From<Arg> for (Host, RequestsToSend, TransmissionInterval) {
+    fn from(arg: Arg) -> Self {
+        (arg.destination, arg.requests, arg.interval)
+    }
+}
+
+pub fn parse_arg(arg: &str) -> Result<Arg, Error> {
+    let mut comma_separated_values = arg.split(',').take(3);
+    let destination = comma_separated_values.next();
+    let requests = comma_separated_values.next();
+    let interval = comma_separated_values.next();
+    let (destination, requests, interval) =
+        destination.and_then(|destination| requests.and_then(|requests|
+            interval.map(|interval| (destination, requests, interval))
+        )).ok_or_else(|| "Usage of ICMP Ping requires an argument consisting of a comma-delimited list of IP address or host name, number of requests (or forever), and ping interval".to_string())?;
+    let destination = Host::from(destination);
+    let requests: RequestsToSend = requests.try_into()?;
+    let interval: TransmissionInterval = interval.try_into()?;
+    Ok((destination, requests, interval).into())
+}
+
+/// Reads one comma-delimited argument per line, skipping blank lines and
+/// lines starting with `#`.
+pub fn read_args<P: AsRef<Path>>(path: P) -> Result<Vec<Arg>, Error> {
+    fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(parse_arg)
+        .collect()
+}
@@ -0,0 +1,33 @@
+use {
+    derive_more::{From, TryInto},
+    icmp_socket::packet::IcmpPacketBuildError,
+    std::{
+        fmt::{self, Display, Formatter},
+        net::AddrParseError,
+        num::ParseIntError,
+    },
+};
+
+#[derive(Debug, From, TryInto)]
+pub enum Error {
+    AddressParsing(AddrParseError),
+    Io(std::io::Error),
+    NumberParsing(ParseIntError),
+    PacketBuilding(IcmpPacketBuildError),
+    Uncategorized(String),
+}
+
+/// Formats the message of the error within, which is what users get to see.
+impl Display for Error {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AddressParsing(error) => error.fmt(formatter),
+            Self::Io(error) => error.fmt(formatter),
+            Self::NumberParsing(error) => error.fmt(formatter),
+            Self::PacketBuilding(error) => error.fmt(formatter),
+            Self::Uncategorized(message) => message.fmt(formatter),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,286 @@
+use {
+    crate::{Integrity, Jitter, Tos},
+    derive_more::From,
+    std::{
+        fmt::{self, Display, Formatter},
+        io,
+        net::IpAddr,
+        sync::Arc,
+        time::{Duration, SystemTime},
+    },
+};
+
+/// One echo reply, attributed to the probe that produced it.
+#[derive(Clone, Debug)]
+pub struct Reply {
+    pub destination: IpAddr,
+    pub responder: IpAddr,
+    pub sequence: u16,
+    pub round_trip: Duration,
+    /// The variation of the round trip times of the destination's replies up
+    /// to this one, which a [`Summary`](crate::Summary) takes its jitter
+    /// from.
+    pub variation: Jitter,
+    /// The RFC 3550 jitter out of `variation`, if the pinger was asked to
+    /// report it with [`with_jitter`](crate::Pinger::with_jitter).
+    pub jitter: Option<Duration>,
+    /// The reply's IPv4 time to live or IPv6 hop limit.
+    pub ttl: Option<u8>,
+    /// The marking the request was sent with, if one was set.
+    pub sent_tos: Option<Tos>,
+    /// The reply's IPv4 type of service or IPv6 traffic class, where the
+    /// socket reports it.
+    pub reply_tos: Option<Tos>,
+    /// Whether the reply echoed the request's payload unchanged.
+    pub integrity: Integrity,
+    /// What timed the request's departure.
+    pub sent_clock: Clock,
+    /// What timed the reply's arrival.
+    pub clock: Clock,
+    /// Whether the probe had been answered already, making this a copy such
+    /// as a duplicating link produces, which summaries leave out.
+    pub duplicate: bool,
+    /// Wall clock time at which the reply arrived.
+    pub timestamp: SystemTime,
+}
+
+/// Where the time a request left or a reply arrived came from, from the
+/// least precise to the most.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, strum_macros::Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum Clock {
+    /// Read in user space, right before the call that hands the request to
+    /// the kernel, or after the reply was received, so scheduling delays
+    /// count.
+    User,
+    /// Stamped by the kernel as the request went out or the reply came in.
+    Kernel,
+    /// Stamped by the network card.
+    Hardware,
+}
+
+impl Reply {
+    /// Whether the reply came back with another DSCP than the request was
+    /// sent with.
+    pub fn remarked(&self) -> bool {
+        matches!(
+            (self.sent_tos, self.reply_tos),
+            (Some(sent), Some(received)) if sent.dscp() != received.dscp()
+        )
+    }
+}
+
+/// Formats the reply as the `address,sequence,microseconds` line printed by
+/// the binary, with the destination's address like every other line rather
+/// than the responder's, followed by the jitter in microseconds where it is
+/// reported, by the marking sent and the one received where the request was
+/// marked, by `,truncated` or `,corrupted` if the payload did not come back
+/// intact, by `,remarked` if its DSCP changed and by `,duplicate` if it is a
+/// copy.
+impl Display for Reply {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            formatter,
+            "{},{},{}",
+            self.destination,
+            self.sequence,
+            self.round_trip.as_micros()
+        )?;
+        if let Some(jitter) = self.jitter {
+            write!(formatter, ",{}", jitter.as_micros())?;
+        }
+        if let Some(sent) = self.sent_tos {
+            let received = self.reply_tos.map(|tos| tos.to_string());
+            write!(formatter, ",{},{}", sent, received.unwrap_or_default())?;
+        }
+        match self.integrity {
+            Integrity::Intact => Ok(()),
+            integrity => write!(formatter, ",{}", integrity),
+        }?;
+        if self.remarked() {
+            write!(formatter, ",remarked")?;
+        }
+        match self.duplicate {
+            true => write!(formatter, ",duplicate"),
+            false => Ok(()),
+        }
+    }
+}
+
+/// A probe that saw no reply within the timeout. Its reply may still show up
+/// later, as a [`Reply`] for the same sequence.
+#[derive(Clone, Debug)]
+pub struct Timeout {
+    pub destination: IpAddr,
+    pub sequence: u16,
+    /// Wall clock time at which the probe timed out.
+    pub timestamp: SystemTime,
+}
+
+/// Formats the timeout as an `address,sequence,timeout` line.
+impl Display for Timeout {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+        write!(formatter, "{},{},timeout", self.destination, self.sequence)
+    }
+}
+
+/// Why a router or host sent an ICMP error back instead of a reply.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reason {
+    /// The request's time to live or hop limit ran out on the way.
+    TimeExceeded,
+    /// The request was too large for the next hop and could not be
+    /// fragmented, with the next hop's MTU where the router gave it. ICMPv6
+    /// calls this Packet Too Big.
+    FragmentationNeeded { mtu: Option<u32> },
+    /// The request could not be delivered, for one of the reasons
+    /// Destination Unreachable codes give.
+    Unreachable(Unreachable),
+    /// A router or host asked for requests to be sent more slowly, and may
+    /// have dropped this one. ICMPv4 only, and long deprecated.
+    SourceQuench,
+    /// A router forwarded the request but pointed at a better first hop for
+    /// the destination. ICMPv4 only.
+    Redirect { gateway: IpAddr },
+    /// Something in the request's headers could not be processed, at this
+    /// byte offset where the error says.
+    ParameterProblem { pointer: Option<u32> },
+}
+
+impl Display for Reason {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TimeExceeded => write!(formatter, "time exceeded"),
+            Self::FragmentationNeeded { mtu: None } => write!(formatter, "fragmentation needed"),
+            Self::FragmentationNeeded { mtu: Some(mtu) } => {
+                write!(formatter, "fragmentation needed (mtu {})", mtu)
+            }
+            Self::Unreachable(unreachable) => write!(formatter, "{}", unreachable),
+            Self::SourceQuench => write!(formatter, "source quench"),
+            Self::Redirect { gateway } => write!(formatter, "redirect to {}", gateway),
+            Self::ParameterProblem { pointer: None } => write!(formatter, "parameter problem"),
+            Self::ParameterProblem {
+                pointer: Some(pointer),
+            } => write!(formatter, "parameter problem (pointer {})", pointer),
+        }
+    }
+}
+
+/// What was unreachable, from the codes of ICMPv4 and ICMPv6 Destination
+/// Unreachable messages, folded together where they mean the same.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Unreachable {
+    /// No route to the destination's network.
+    Network,
+    /// The destination's network is reachable but the host is not, as when
+    /// it does not answer ARP or neighbour solicitations.
+    Host,
+    Protocol,
+    Port,
+    /// A filter on the way rejected the request.
+    Prohibited,
+    SourceRouteFailed,
+    /// A code the pinger has no name for.
+    Other {
+        code: u8,
+    },
+}
+
+impl Display for Unreachable {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Network => write!(formatter, "network unreachable"),
+            Self::Host => write!(formatter, "host unreachable"),
+            Self::Protocol => write!(formatter, "protocol unreachable"),
+            Self::Port => write!(formatter, "port unreachable"),
+            Self::Prohibited => write!(formatter, "administratively prohibited"),
+            Self::SourceRouteFailed => write!(formatter, "source route failed"),
+            Self::Other { code } => write!(formatter, "destination unreachable (code {})", code),
+        }
+    }
+}
+
+/// An ICMP error that quoted one of the probes, which settles that probe
+/// unless it is a [`Reason::Redirect`].
+#[derive(Clone, Debug)]
+pub struct Undelivered {
+    pub destination: IpAddr,
+    /// The router or host that sent the error.
+    pub responder: IpAddr,
+    pub sequence: u16,
+    pub round_trip: Duration,
+    pub reason: Reason,
+    /// Whether the probe had been answered already, by a reply or another
+    /// error, which summaries then leave this one out for.
+    pub duplicate: bool,
+    /// Wall clock time at which the error arrived.
+    pub timestamp: SystemTime,
+}
+
+/// Formats the error as an `address,sequence,reason from responder` line,
+/// e.g. `10.1.2.3,4,time exceeded from 10.0.0.1`, followed by `,duplicate`
+/// if the probe was answered before.
+impl Display for Undelivered {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            formatter,
+            "{},{},{} from {}",
+            self.destination, self.sequence, self.reason, self.responder
+        )?;
+        match self.duplicate {
+            true => write!(formatter, ",duplicate"),
+            false => Ok(()),
+        }
+    }
+}
+
+/// An echo request the kernel refused to send, e.g. for want of a route or
+/// because a firewall rule rejected it. Nothing went out, so there is no
+/// reply or timeout to wait for.
+#[derive(Clone, Debug)]
+pub struct SendFailure {
+    pub destination: IpAddr,
+    pub sequence: u16,
+    pub error: Arc<io::Error>,
+    /// Wall clock time at which the send failed.
+    pub timestamp: SystemTime,
+}
+
+/// Formats the failure as an `address,sequence,send failed: error` line.
+impl Display for SendFailure {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            formatter,
+            "{},{},send failed: {}",
+            self.destination, self.sequence, self.error
+        )
+    }
+}
+
+/// Everything a [`Pinger`](crate::Pinger) observes, in the order it happens.
+#[derive(Clone, Debug, From)]
+pub enum Event {
+    /// An echo request with this sequence number went out.
+    #[from(ignore)]
+    Sent {
+        destination: IpAddr,
+        sequence: u16,
+    },
+    Reply(Reply),
+    Timeout(Timeout),
+    Undelivered(Undelivered),
+    SendFailure(SendFailure),
+}
+
+impl Event {
+    /// The destination whose probe this event is about.
+    pub fn destination(&self) -> IpAddr {
+        match self {
+            Self::Sent { destination, .. } => *destination,
+            Self::Reply(reply) => reply.destination,
+            Self::Timeout(timeout) => timeout.destination,
+            Self::Undelivered(undelivered) => undelivered.destination,
+            Self::SendFailure(failure) => failure.destination,
+        }
+    }
+}
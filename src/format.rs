@@ -0,0 +1,262 @@
+use {
+    crate::{Discovery, Error, Event, Fit, Hop, Summary, PERCENTILES},
+    serde_json::{json, Map, Value},
+    std::{
+        str::FromStr,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    },
+};
+
+/// How events and summaries are written out: comma-separated lines, or one
+/// JSON object per line with named fields.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("unknown format {}, expected csv or json", text).into()),
+        }
+    }
+}
+
+impl Format {
+    /// The line for `event`, if it is one that gets written out.
+    pub fn event(self, event: &Event) -> Option<String> {
+        match (self, event) {
+            (_, Event::Sent { .. }) => None,
+            (Self::Csv, Event::Reply(reply)) => Some(reply.to_string()),
+            (Self::Csv, Event::Timeout(timeout)) => Some(timeout.to_string()),
+            (Self::Csv, Event::Undelivered(undelivered)) => Some(undelivered.to_string()),
+            (Self::Csv, Event::SendFailure(failure)) => Some(failure.to_string()),
+            (Self::Json, Event::Reply(reply)) => Some(
+                json!({
+                    "type": "reply",
+                    "destination": reply.destination,
+                    "responder": reply.responder,
+                    "sequence": reply.sequence,
+                    "rtt_us": reply.round_trip.as_micros() as u64,
+                    "jitter_us": reply.jitter.map(|jitter| jitter.as_micros() as u64),
+                    "ttl": reply.ttl,
+                    "tos": reply.sent_tos.map(|tos| tos.to_string()),
+                    "reply_tos": reply.reply_tos.map(|tos| tos.to_string()),
+                    "payload": reply.integrity.to_string(),
+                    "sent_clock": reply.sent_clock.to_string(),
+                    "clock": reply.clock.to_string(),
+                    "duplicate": reply.duplicate,
+                    "timestamp": seconds(reply.timestamp),
+                })
+                .to_string(),
+            ),
+            (Self::Json, Event::Timeout(timeout)) => Some(
+                json!({
+                    "type": "timeout",
+                    "destination": timeout.destination,
+                    "sequence": timeout.sequence,
+                    "timestamp": seconds(timeout.timestamp),
+                })
+                .to_string(),
+            ),
+            (Self::Json, Event::Undelivered(undelivered)) => Some(
+                json!({
+                    "type": "undelivered",
+                    "destination": undelivered.destination,
+                    "responder": undelivered.responder,
+                    "sequence": undelivered.sequence,
+                    "rtt_us": undelivered.round_trip.as_micros() as u64,
+                    "reason": undelivered.reason.to_string(),
+                    "duplicate": undelivered.duplicate,
+                    "timestamp": seconds(undelivered.timestamp),
+                })
+                .to_string(),
+            ),
+            (Self::Json, Event::SendFailure(failure)) => Some(
+                json!({
+                    "type": "send_error",
+                    "destination": failure.destination,
+                    "sequence": failure.sequence,
+                    "error": failure.error.to_string(),
+                    "errno": failure.error.raw_os_error(),
+                    "timestamp": seconds(failure.timestamp),
+                })
+                .to_string(),
+            ),
+        }
+    }
+
+    /// The summary as one more line in this format.
+    pub fn summary(self, summary: &Summary) -> String {
+        let micros = |rtt: Option<Duration>| rtt.map(|rtt| rtt.as_micros() as u64);
+        match self {
+            Self::Csv => summary.csv(),
+            Self::Json => {
+                let head = json!({
+                    "type": "summary",
+                    "destination": summary.destination,
+                    "host": summary.host.to_string(),
+                    "source": summary.source,
+                    "interface": summary.interface,
+                    "transmitted": summary.transmitted,
+                    "received": summary.received,
+                    "loss": summary.loss(),
+                    "min_us": micros(summary.min()),
+                    "avg_us": micros(summary.avg()),
+                    "max_us": micros(summary.max()),
+                    "mdev_us": micros(summary.mdev()),
+                });
+                // p50_us, p90_us, ..., p99_9_us
+                let percentiles = PERCENTILES.iter().map(|percentile| {
+                    (
+                        format!("p{}_us", percentile).replace('.', "_"),
+                        json!(micros(summary.percentile(*percentile))),
+                    )
+                });
+                let tail = json!({
+                    "jitter_us": micros(summary.jitter()),
+                    "mean_difference_us": micros(summary.mean_difference()),
+                    "errors": summary.errors,
+                    "send_errors": summary.send_errors,
+                    "duplicates": summary.duplicates,
+                    "timestamp": seconds(SystemTime::now()),
+                });
+                Value::Object(
+                    fields(head)
+                        .into_iter()
+                        .chain(percentiles)
+                        .chain(fields(tail))
+                        .collect(),
+                )
+                .to_string()
+            }
+        }
+    }
+
+    /// One destination's round trip times: a text histogram headed like the
+    /// trailer, or one object holding every non-empty bucket as its lower
+    /// bound, upper bound and count, which histograms of other runs can be
+    /// merged with.
+    pub fn histogram(self, summary: &Summary) -> String {
+        match self {
+            Self::Csv => format!(
+                "--- {} rtt histogram ---\n{}",
+                summary.destination,
+                summary.histogram()
+            )
+            .trim_end()
+            .to_string(),
+            Self::Json => json!({
+                "type": "histogram",
+                "destination": summary.destination,
+                "host": summary.host.to_string(),
+                "buckets": summary
+                    .histogram()
+                    .buckets()
+                    .map(|(lower, upper, count)| {
+                        json!([lower.as_micros() as u64, upper.as_micros() as u64, count])
+                    })
+                    .collect::<Vec<_>>(),
+                "timestamp": seconds(SystemTime::now()),
+            })
+            .to_string(),
+        }
+    }
+
+    /// The line for a step of path MTU discovery.
+    pub fn discovery(self, discovery: &Discovery) -> String {
+        match (self, discovery) {
+            (Self::Csv, Discovery::Probe(probe)) => probe.to_string(),
+            (Self::Csv, Discovery::Found(found)) => found.to_string(),
+            (Self::Json, Discovery::Probe(probe)) => {
+                let (mtu, responder) = match probe.fit {
+                    Fit::TooBig { mtu, responder } => (mtu, responder),
+                    Fit::Fits | Fit::Lost => (None, None),
+                };
+                json!({
+                    "type": "mtu_probe",
+                    "destination": probe.destination,
+                    "size": probe.size,
+                    "packet": probe.packet(),
+                    "fit": match probe.fit {
+                        Fit::Fits => "fits",
+                        Fit::TooBig { .. } => "too big",
+                        Fit::Lost => "lost",
+                    },
+                    "mtu": mtu,
+                    "responder": responder,
+                    "timestamp": seconds(probe.timestamp),
+                })
+                .to_string()
+            }
+            (Self::Json, Discovery::Found(found)) => json!({
+                "type": "path_mtu",
+                "destination": found.destination,
+                "host": found.host.to_string(),
+                "mtu": found.mtu(),
+                "size": found.size,
+                "timestamp": seconds(SystemTime::now()),
+            })
+            .to_string(),
+        }
+    }
+
+    /// The line reporting one hop of a trace.
+    pub fn hop(self, hop: &Hop) -> String {
+        match self {
+            Self::Csv => hop.to_string(),
+            Self::Json => json!({
+                "type": "hop",
+                "destination": hop.destination,
+                "hop": hop.hop,
+                "reached": hop.reached,
+                "probes": hop
+                    .probes
+                    .iter()
+                    .map(|probe| probe.map(|reply| json!({
+                        "responder": reply.responder,
+                        "rtt_us": reply.round_trip.as_micros() as u64,
+                        "unreachable": reply.unreachable.map(|unreachable| unreachable.to_string()),
+                    })))
+                    .collect::<Vec<_>>(),
+                "timestamp": seconds(hop.timestamp),
+            })
+            .to_string(),
+        }
+    }
+
+    /// The line reporting a run that failed, if this format has one.
+    pub fn error(self, error: &Error) -> Option<String> {
+        match self {
+            Self::Csv => None,
+            Self::Json => Some(
+                json!({
+                    "type": "error",
+                    "error": error.to_string(),
+                    "timestamp": seconds(SystemTime::now()),
+                })
+                .to_string(),
+            ),
+        }
+    }
+}
+
+/// The fields of a JSON object.
+fn fields(object: Value) -> Map<String, Value> {
+    match object {
+        Value::Object(fields) => fields,
+        _ => Map::new(),
+    }
+}
+
+/// Seconds since the Unix epoch, with microsecond precision.
+fn seconds(timestamp: SystemTime) -> f64 {
+    timestamp
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_micros() as f64 / 1e6)
+        .unwrap_or_default()
+}
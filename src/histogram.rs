@@ -0,0 +1,311 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    time::Duration,
+};
+
+/// Values below this many microseconds get a bucket each; above it, every
+/// doubling is split into half as many buckets of equal width.
+const SUB_BUCKETS: u64 = 128;
+
+/// Rows of the text histogram per doubling of the round trip time.
+const ROWS_PER_OCTAVE: u64 = 4;
+
+/// Width of the longest bar of the text histogram.
+const BAR_WIDTH: u64 = 40;
+
+/// Round trip times counted in log-linear buckets, the way HDR histograms do:
+/// exact to the microsecond up to 128 µs and within 1/64 of the value above,
+/// in a few thousand counters at most however long the run.
+///
+/// The bucket boundaries are fixed, so histograms of separate runs merge by
+/// adding up the counts of equal buckets, either with
+/// [`merge`](Self::merge) or by collecting exported `(lower bound, count)`
+/// pairs back into one.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    total: u64,
+}
+
+impl Histogram {
+    pub fn record(&mut self, rtt: Duration) {
+        self.record_count(rtt, 1)
+    }
+
+    fn record_count(&mut self, rtt: Duration, count: u64) {
+        let index = index(rtt.as_micros().try_into().unwrap_or(u64::MAX));
+        if self.counts.len() <= index {
+            self.counts.resize(index + 1, 0);
+        }
+        self.counts[index] += count;
+        self.total += count;
+    }
+
+    /// Adds the counts of `other` to these.
+    pub fn merge(&mut self, other: &Self) {
+        other
+            .buckets()
+            .for_each(|(lower, _, count)| self.record_count(lower, count));
+    }
+
+    /// How many round trip times were recorded.
+    pub fn count(&self) -> u64 {
+        self.total
+    }
+
+    /// The round trip time that `percentile` percent of the recorded ones do
+    /// not exceed, as the upper bound of its bucket.
+    pub fn percentile(&self, percentile: f64) -> Option<Duration> {
+        let rank = (percentile / 100.0 * self.total as f64).ceil().max(1.0) as u64;
+        self.buckets()
+            .scan(0, |seen, (_, upper, count)| {
+                *seen += count;
+                Some((*seen, upper))
+            })
+            .find(|(seen, _)| *seen >= rank.min(self.total))
+            .map(|(_, upper)| upper)
+    }
+
+    /// Every bucket holding at least one round trip time, as its lower and
+    /// upper bound and its count, from the fastest up.
+    pub fn buckets(&self) -> impl Iterator<Item = (Duration, Duration, u64)> + '_ {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(index, count)| {
+                let (lower, upper) = bounds(index);
+                (
+                    Duration::from_micros(lower),
+                    Duration::from_micros(upper),
+                    *count,
+                )
+            })
+    }
+}
+
+/// Rebuilds a histogram from `(round trip time, count)` pairs, such as the
+/// lower bounds and counts of exported buckets.
+impl FromIterator<(Duration, u64)> for Histogram {
+    fn from_iter<I: IntoIterator<Item = (Duration, u64)>>(buckets: I) -> Self {
+        buckets
+            .into_iter()
+            .fold(Self::default(), |mut histogram, (rtt, count)| {
+                histogram.record_count(rtt, count);
+                histogram
+            })
+    }
+}
+
+/// Draws the histogram as one line per quarter doubling of the round trip
+/// time, from the fastest to the slowest recorded, each with the lower bound
+/// of its range in milliseconds, a bar and a count.
+impl Display for Histogram {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+        let rows = self
+            .buckets()
+            .fold(Vec::<(u64, u64)>::new(), |mut rows, (lower, _, count)| {
+                let row = row(lower.as_micros() as u64);
+                match rows.last_mut() {
+                    Some((last, total)) if *last == row => *total += count,
+                    _ => rows.push((row, count)),
+                }
+                rows
+            });
+        let (first, last) = match (rows.first(), rows.last()) {
+            (Some((first, _)), Some((last, _))) => (*first, *last),
+            _ => return Ok(()),
+        };
+        let widest = rows.iter().map(|(_, count)| *count).max().unwrap_or(1);
+        (first..=last).try_for_each(|row| {
+            let count = rows
+                .iter()
+                .find(|(other, _)| *other == row)
+                .map_or(0, |(_, count)| *count);
+            let bar = (count * BAR_WIDTH).div_ceil(widest) as usize;
+            writeln!(
+                formatter,
+                "{:>10.3} ms |{:<width$}| {}",
+                row_start(row) as f64 / 1e3,
+                "#".repeat(bar),
+                count,
+                width = BAR_WIDTH as usize
+            )
+        })
+    }
+}
+
+/// The bucket of a round trip time in microseconds.
+fn index(micros: u64) -> usize {
+    let magnitude =
+        (u64::BITS - micros.leading_zeros()).saturating_sub(SUB_BUCKETS.trailing_zeros());
+    (u64::from(magnitude) * SUB_BUCKETS / 2 + (micros >> magnitude)) as usize
+}
+
+/// The lowest and highest round trip time in microseconds that fall in a
+/// bucket.
+fn bounds(index: usize) -> (u64, u64) {
+    let index = index as u64;
+    let magnitude = (index / (SUB_BUCKETS / 2)).saturating_sub(1);
+    let lower = (index - magnitude * SUB_BUCKETS / 2) << magnitude;
+    (lower, lower + ((1 << magnitude) - 1))
+}
+
+/// The row of the text histogram a round trip time in microseconds is drawn
+/// in: one per value below four, then four equally wide rows per doubling.
+fn row(micros: u64) -> u64 {
+    match micros {
+        0..=3 => micros,
+        _ => {
+            let octave = u64::from(micros.ilog2());
+            ROWS_PER_OCTAVE * (octave - 1) + ((micros - (1 << octave)) >> (octave - 2))
+        }
+    }
+}
+
+/// The lowest round trip time in microseconds drawn in a row.
+fn row_start(row: u64) -> u64 {
+    match row {
+        0..=3 => row,
+        _ => {
+            let octave = row / ROWS_PER_OCTAVE + 1;
+            (1 << octave) + ((row % ROWS_PER_OCTAVE) << (octave - 2))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Values to check the bucket and row functions at: every value up to
+    /// well past where buckets start to widen, and those at the top of the
+    /// range.
+    fn samples() -> impl Iterator<Item = u64> {
+        (0..=1024).chain(u64::MAX - 1024..=u64::MAX)
+    }
+
+    #[test]
+    fn buckets_hold_their_values() {
+        samples().for_each(|micros| {
+            let (lower, upper) = bounds(index(micros));
+            assert!(
+                lower <= micros && micros <= upper,
+                "{} in {}..={}",
+                micros,
+                lower,
+                upper
+            );
+        });
+    }
+
+    #[test]
+    fn buckets_are_contiguous() {
+        samples()
+            .filter(|micros| *micros < u64::MAX)
+            .for_each(|micros| {
+                let step = index(micros + 1) - index(micros);
+                assert!(
+                    step <= 1,
+                    "{} buckets between {} and {}",
+                    step,
+                    micros,
+                    micros + 1
+                );
+            });
+        // Where the buckets start to widen, and where they widen again.
+        [127, 255].into_iter().for_each(|micros| {
+            assert_eq!(index(micros + 1), index(micros) + 1, "after {}", micros);
+        });
+        (0..index(u64::MAX)).for_each(|index| {
+            assert_eq!(
+                bounds(index).1 + 1,
+                bounds(index + 1).0,
+                "after bucket {}",
+                index
+            )
+        });
+        assert_eq!(bounds(index(u64::MAX)).1, u64::MAX);
+    }
+
+    #[test]
+    fn buckets_are_exact_up_to_128() {
+        (0..128).for_each(|micros| assert_eq!(bounds(index(micros)), (micros, micros)));
+        assert_eq!(bounds(index(128)), (128, 129));
+        assert_eq!(bounds(index(256)), (256, 259));
+    }
+
+    #[test]
+    fn rows_hold_their_values() {
+        samples().for_each(|micros| {
+            let drawn = row(micros);
+            assert!(row_start(drawn) <= micros, "{} in row {}", micros, drawn);
+            if drawn < row(u64::MAX) {
+                assert!(micros < row_start(drawn + 1), "{} in row {}", micros, drawn);
+            }
+        });
+        (0..=row(u64::MAX)).for_each(|drawn| assert_eq!(row(row_start(drawn)), drawn));
+        assert_eq!([3, 4, 5, 6, 7, 8, 10].map(row), [3, 4, 5, 6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn percentiles_take_the_nearest_rank() {
+        let histogram = (1..=100)
+            .map(|micros| (Duration::from_micros(micros), 1))
+            .collect::<Histogram>();
+        let percentile = |percentile| histogram.percentile(percentile).map(|rtt| rtt.as_micros());
+        assert_eq!(percentile(0.0), Some(1));
+        assert_eq!(percentile(50.0), Some(50));
+        assert_eq!(percentile(90.0), Some(90));
+        assert_eq!(percentile(99.9), Some(100));
+        assert_eq!(percentile(100.0), Some(100));
+        assert_eq!(Histogram::default().percentile(50.0), None);
+    }
+
+    #[test]
+    fn percentiles_report_the_upper_bound() {
+        let mut histogram = Histogram::default();
+        histogram.record(Duration::from_micros(41214));
+        assert_eq!(
+            histogram.percentile(50.0),
+            Some(Duration::from_micros(41471))
+        );
+    }
+
+    #[test]
+    fn merging_adds_up_counts() {
+        let rtts = |micros: &[u64]| {
+            micros
+                .iter()
+                .fold(Histogram::default(), |mut histogram, micros| {
+                    histogram.record(Duration::from_micros(*micros));
+                    histogram
+                })
+        };
+        let mut merged = rtts(&[10, 200, 200]);
+        merged.merge(&rtts(&[201, 5000]));
+        assert_eq!(merged, rtts(&[10, 200, 200, 201, 5000]));
+        assert_eq!(merged.count(), 5);
+        assert_eq!(
+            merged
+                .buckets()
+                .map(|(.., count)| count)
+                .collect::<Vec<_>>(),
+            [1, 3, 1]
+        );
+    }
+
+    #[test]
+    fn exported_buckets_collect_back() {
+        let histogram = [7, 130, 131, 54323, 55129, 55130]
+            .into_iter()
+            .map(|micros| (Duration::from_micros(micros), 1))
+            .collect::<Histogram>();
+        let collected = histogram
+            .buckets()
+            .map(|(lower, _, count)| (lower, count))
+            .collect::<Histogram>();
+        assert_eq!(collected, histogram);
+        assert_eq!(collected.count(), 6);
+    }
+}
@@ -0,0 +1,118 @@
+use std::time::Duration;
+
+/// Variation of the round trip times of one destination's replies, taken
+/// from the difference between each reply's round trip time and that of the
+/// reply before it.
+///
+/// Replies are compared in the order they arrive, as RFC 3550 does for
+/// packets received out of sequence. A lost probe is simply skipped, so the
+/// reply after it is compared with the last one that did arrive rather than
+/// counted as a difference of its own.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Jitter {
+    last: Option<Duration>,
+    /// The smoothed jitter, in microseconds.
+    smoothed: f64,
+    /// Sum of the absolute differences, in microseconds, and their number.
+    total: f64,
+    differences: u64,
+}
+
+impl Jitter {
+    pub fn record(&mut self, rtt: Duration) {
+        if let Some(last) = self.last {
+            let difference = rtt.abs_diff(last).as_secs_f64() * 1e6;
+            self.smoothed += (difference - self.smoothed) / 16.0;
+            self.total += difference;
+            self.differences += 1;
+        }
+        self.last = Some(rtt);
+    }
+
+    /// The RFC 3550 interarrival jitter: a running average of the absolute
+    /// differences with a gain of 1/16. Zero after the first reply, as the
+    /// RFC starts it, and `None` before.
+    pub fn smoothed(&self) -> Option<Duration> {
+        self.last
+            .map(|_| Duration::from_secs_f64(self.smoothed / 1e6))
+    }
+
+    /// The mean absolute difference between successive round trip times,
+    /// once there are two of them.
+    pub fn mean_difference(&self) -> Option<Duration> {
+        match self.differences {
+            0 => None,
+            differences => Some(Duration::from_secs_f64(
+                self.total / differences as f64 / 1e6,
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recorded(micros: &[u64]) -> Jitter {
+        micros.iter().fold(Jitter::default(), |mut jitter, micros| {
+            jitter.record(Duration::from_micros(*micros));
+            jitter
+        })
+    }
+
+    fn micros(duration: Option<Duration>) -> Option<f64> {
+        duration.map(|duration| duration.as_secs_f64() * 1e6)
+    }
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.expect("a value");
+        assert!(
+            (actual - expected).abs() < 1e-3,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn nothing_before_the_first_reply() {
+        let jitter = Jitter::default();
+        assert_eq!(jitter.smoothed(), None);
+        assert_eq!(jitter.mean_difference(), None);
+    }
+
+    #[test]
+    fn zero_after_the_first_reply() {
+        let jitter = recorded(&[54323]);
+        assert_eq!(jitter.smoothed(), Some(Duration::ZERO));
+        assert_eq!(jitter.mean_difference(), None);
+    }
+
+    #[test]
+    fn follows_the_rfc_3550_recurrence() {
+        let rtts = [1000, 1500, 900, 4000, 4000, 120, 3000, 2999];
+        let expected = rtts.windows(2).fold(0.0, |jitter: f64, pair| {
+            let difference = (pair[1] as f64 - pair[0] as f64).abs();
+            jitter + (difference - jitter) / 16.0
+        });
+        let jitter = recorded(&rtts);
+        assert_close(micros(jitter.smoothed()), expected);
+        assert_close(micros(jitter.mean_difference()), 10961.0 / 7.0);
+    }
+
+    #[test]
+    fn matches_the_readme() {
+        // The differences are 806, 17900, 1360 and 5345 µs.
+        let jitter = recorded(&[54323, 55129, 37229, 35869, 41214]);
+        assert_close(micros(jitter.smoothed()), 1438.5341);
+        assert_close(micros(jitter.mean_difference()), 6352.75);
+    }
+
+    #[test]
+    fn differences_count_either_way() {
+        let (rising, falling) = (recorded(&[100, 300, 100]), recorded(&[300, 100, 300]));
+        assert_eq!(rising.smoothed(), falling.smoothed());
+        assert_eq!(rising.mean_difference(), Some(Duration::from_micros(200)));
+        assert_eq!(falling.mean_difference(), Some(Duration::from_micros(200)));
+    }
+}
@@ -0,0 +1,39 @@
+//! ICMP echo ("ping") as a library.
+//!
+//! A [`Pinger`] is built from one or more [`Arg`]s, the same
+//! destination/requests/interval triples the `icmp-echo` binary accepts on its
+//! command line, and yields an [`Event`] per request sent, reply received and
+//! probe timed out instead of printing them. A [`Summary`] folds one
+//! destination's events into the closing statistics.
+
+mod arg;
+mod error;
+mod event;
+mod format;
+mod histogram;
+mod jitter;
+mod mtu;
+mod payload;
+mod pinger;
+mod resolve;
+mod rounds;
+mod socket;
+mod summary;
+mod tos;
+mod trace;
+
+pub use {
+    arg::{parse_arg, read_args, Arg, RequestsToSend, TransmissionInterval},
+    error::Error,
+    event::{Clock, Event, Reason, Reply, SendFailure, Timeout, Undelivered, Unreachable},
+    format::Format,
+    histogram::Histogram,
+    jitter::Jitter,
+    mtu::{Discovery, Fit, MtuProbe, MtuSearch, PathMtu},
+    payload::{Integrity, Pattern, Payload, MAX_PAYLOAD, STAMP_LENGTH},
+    pinger::Pinger,
+    resolve::{Family, Host, Resolver},
+    summary::{Summary, PERCENTILES},
+    tos::Tos,
+    trace::{Hop, HopReply, Trace},
+};
@@ -1,144 +1,273 @@
 use {
-    derive_more::{From, Into, TryInto},
-    futures_util::{stream::iter, FutureExt, StreamExt, TryFutureExt, TryStreamExt},
-    icmp_socket::{
-        packet::{IcmpPacketBuildError, WithEchoRequest},
-        IcmpSocket, IcmpSocket4, Icmpv4Message, Icmpv4Packet,
-    },
-    std::{
-        net::{AddrParseError, Ipv4Addr},
-        num::ParseIntError,
-        str::FromStr,
-        time::{Duration, Instant},
+    futures_util::{future::ready, stream::iter, StreamExt, TryStreamExt},
+    icmp_echo::{
+        parse_arg, read_args, Arg, Error, Family, Format, MtuSearch, Pattern, Payload, Pinger,
+        Resolver, Summary, Tos, Trace, MAX_PAYLOAD,
     },
+    std::{fs, net::IpAddr, path::PathBuf, time::Duration},
     structopt::StructOpt,
 };
 
-#[derive(Debug, From, Into)]
-struct RequestsToSend(u16);
-
-impl<'a> TryFrom<&'a str> for RequestsToSend {
-    type Error = Error;
-    fn try_from(text: &'a str) -> Result<Self, Self::Error> {
-        match u16::from_str(text)? {
-            x if x > 10 => Err("only ten or less requests are supported".to_string().into()),
-            x if x == 0 => Err("at least one ping must be requested".to_string().into()),
-            x => Ok(x.into()),
-        }
-    }
+#[derive(Debug, StructOpt)]
+struct Options {
+    /// Destinations to ping side by side, each as address,requests,interval
+    #[structopt(parse(try_from_str = parse_arg), required_unless = "file")]
+    args: Vec<Arg>,
+    /// Read more destinations from this file, one per line
+    #[structopt(long, parse(from_os_str))]
+    file: Option<PathBuf>,
+    /// ICMP echo identifier; defaults to the low 16 bits of the process id
+    #[structopt(long)]
+    identifier: Option<u16>,
+    /// Milliseconds to wait for each reply before reporting a timeout
+    #[structopt(long, default_value = "5000")]
+    timeout: u64,
+    /// Payload bytes per request including the 20-byte stamp, up to 65507
+    #[structopt(long)]
+    size: Option<usize>,
+    /// Payload fill: random, hex:<digits> or file:<path>; "test packet" by default
+    #[structopt(long)]
+    pattern: Option<Pattern>,
+    /// IPv4 time to live and IPv6 hop limit of every request
+    #[structopt(long, parse(try_from_str = parse_ttl))]
+    ttl: Option<u8>,
+    /// Mark requests with a DSCP name (EF, AF41, CS1...) or a TOS byte
+    #[structopt(long)]
+    tos: Option<Tos>,
+    /// Send from this address, which must be one of the host's own
+    #[structopt(long)]
+    source: Option<IpAddr>,
+    /// Send through this network interface, whatever the routing table says
+    #[structopt(long)]
+    interface: Option<String>,
+    /// Only use IPv4 addresses when resolving the destination
+    #[structopt(short = "4", conflicts_with = "ipv6")]
+    ipv4: bool,
+    /// Only use IPv6 addresses when resolving the destination
+    #[structopt(short = "6")]
+    ipv6: bool,
+    /// Resolve names from this /etc/hosts-style file before asking the system
+    #[structopt(long, parse(from_os_str))]
+    hosts: Option<PathBuf>,
+    /// Output format: csv lines, or json with one object per event
+    #[structopt(long, default_value = "csv", possible_values = &["csv", "json"])]
+    format: Format,
+    /// Print the summary as one more CSV line instead of the ping-style trailer
+    #[structopt(long)]
+    summary_record: bool,
+    /// Add each destination's RFC 3550 jitter so far to its reply lines
+    #[structopt(long)]
+    jitter: bool,
+    /// Print a histogram of each destination's round trip times after its summary
+    #[structopt(long)]
+    histogram: bool,
+    /// Write each destination's round trip time histogram to this file as JSON
+    #[structopt(long, parse(from_os_str))]
+    histogram_file: Option<PathBuf>,
+    /// Discover the path MTU to each destination instead of pinging it, sending
+    /// that destination's number of requests per payload size
+    #[structopt(
+        long,
+        conflicts_with_all = &["size", "trace", "summary-record", "jitter", "histogram", "histogram-file"]
+    )]
+    mtu: bool,
+    /// Smallest payload to try when discovering the path MTU
+    #[structopt(long, default_value = "0")]
+    min_size: usize,
+    /// Largest payload to try when discovering the path MTU, up to 65507
+    #[structopt(long)]
+    max_size: Option<usize>,
+    /// Trace the route to each destination instead of pinging it, sending that
+    /// destination's number of requests per hop
+    #[structopt(
+        long,
+        conflicts_with_all = &["ttl", "summary-record", "jitter", "histogram", "histogram-file"]
+    )]
+    trace: bool,
+    /// Largest time to live or hop limit to try when tracing
+    #[structopt(long, default_value = "30")]
+    max_hops: u8,
 }
 
-#[derive(Clone, Copy, Debug, From, Into)]
-struct TransmissionInterval(u16);
-
-impl<'a> TryFrom<&'a str> for TransmissionInterval {
-    type Error = Error;
-    fn try_from(text: &'a str) -> Result<Self, Self::Error> {
-        match u16::from_str(text)? {
-            x if x > 1000 => Err("only one second or less intervals supported"
-                .to_string()
-                .into()),
-            x if x == 0 => Err("zero interval is not supported".to_string().into()),
-            x => Ok(x.into()),
-        }
+/// Parses a time to live, which must let a request leave the host.
+fn parse_ttl(text: &str) -> Result<u8, Error> {
+    match text.parse()? {
+        0 => Err("a time to live of 0 would not let requests leave the host"
+            .to_string()
+            .into()),
+        ttl => Ok(ttl),
     }
 }
 
-#[derive(Debug)]
-struct Arg {
-    destination: Ipv4Addr,
-    requests: RequestsToSend,
-    interval: TransmissionInterval,
-}
-
-impl From<Options> for (Ipv4Addr, RequestsToSend, TransmissionInterval) {
-    fn from(options: Options) -> Self {
-        let arg = options.arg;
-        (arg.destination, arg.requests, arg.interval)
-    }
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let options = Options::from_args();
+    let format = options.format;
+    run(options).await.inspect_err(|error| {
+        if let Some(line) = format.error(error) {
+            println!("{}", line);
+        }
+    })
 }
 
-impl From<(Ipv4Addr, RequestsToSend, TransmissionInterval)> for Arg {
-    fn from(
-        (destination, requests, interval): (Ipv4Addr, RequestsToSend, TransmissionInterval),
-    ) -> Self {
-        Self {
-            destination,
-            requests,
-            interval,
+async fn run(options: Options) -> Result<(), Error> {
+    let Options {
+        mut args,
+        file,
+        identifier,
+        timeout,
+        size,
+        pattern,
+        ttl,
+        tos,
+        source,
+        interface,
+        ipv4,
+        ipv6,
+        hosts,
+        format,
+        summary_record,
+        jitter,
+        histogram,
+        histogram_file,
+        mtu,
+        min_size,
+        max_size,
+        trace,
+        max_hops,
+    } = options;
+    args.extend(file.map(read_args).transpose()?.into_iter().flatten());
+    let family = match (ipv4, ipv6) {
+        (true, _) => Family::V4,
+        (_, true) => Family::V6,
+        _ => Family::Any,
+    };
+    let resolver = hosts.into_iter().try_fold(
+        Resolver::default().with_family(family),
+        Resolver::with_hosts_file,
+    )?;
+    let setup = move |pinger: Pinger| {
+        let pinger = identifier.into_iter().fold(pinger, Pinger::with_identifier);
+        let pinger = ttl.into_iter().fold(pinger, Pinger::with_ttl);
+        let pinger = tos.into_iter().fold(pinger, Pinger::with_tos);
+        let pinger = source.into_iter().try_fold(pinger, Pinger::with_source)?;
+        interface
+            .iter()
+            .try_fold(pinger, |pinger, interface| pinger.with_interface(interface))
+            .map(|pinger| pinger.with_timeout(Duration::from_millis(timeout)))
+    };
+    let pattern = pattern.unwrap_or_default();
+    match (mtu, trace) {
+        (true, _) => {
+            let searches = args
+                .into_iter()
+                .map(|arg| {
+                    MtuSearch::try_from(setup(Pinger::try_from((arg, &resolver))?)?)?
+                        .with_bounds(min_size, max_size.unwrap_or(MAX_PAYLOAD))
+                        .map(|search| search.with_pattern(pattern.clone()))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            discover(searches, format).await
+        }
+        (_, true) => {
+            let payload = Payload::new(pattern, size)?;
+            let traces = args
+                .into_iter()
+                .map(|arg| {
+                    let pinger = setup(Pinger::try_from((arg, &resolver))?)?;
+                    Trace::try_from(pinger.with_payload(payload.clone()))
+                        .map(|trace| trace.with_max_hops(max_hops))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            route(traces, format).await
+        }
+        (false, false) => {
+            let pinger = setup(Pinger::try_from((args, &resolver))?)?
+                .with_payload(Payload::new(pattern, size)?);
+            let pinger = match jitter {
+                true => pinger.with_jitter(),
+                false => pinger,
+            };
+            ping(pinger, format, summary_record, histogram, histogram_file).await
         }
     }
 }
 
-#[derive(Debug, strum_macros::Display, From, TryInto)]
-enum Error {
-    AddressParsing(AddrParseError),
-    Io(std::io::Error),
-    NumberParsing(ParseIntError),
-    PacketBuilding(IcmpPacketBuildError),
-    Uncategorized(String),
-}
-
-fn parse_arg(arg: &str) -> Result<Arg, Error> {
-    let mut comma_separated_values = arg.split(",").take(3);
-    let destination = comma_separated_values.next();
-    let requests = comma_separated_values.next();
-    let interval = comma_separated_values.next();
-    let (destination, requests, interval) =
-        destination.and_then(|destination| requests.and_then(|requests|
-            interval.map(|interval| (destination, requests, interval))
-        )).ok_or_else(|| "Usage of ICMP Ping requires an argument consisting of a comma-delimited list of IP address, number of requests, and ping interval".to_string())?;
-    let destination = destination.parse::<Ipv4Addr>()?;
-    let requests: RequestsToSend = requests.try_into()?;
-    let interval: TransmissionInterval = interval.try_into()?;
-    Ok((destination, requests, interval).into())
+/// Runs the searches one after the other, printing every step.
+async fn discover(searches: Vec<MtuSearch>, format: Format) -> Result<(), Error> {
+    iter(searches)
+        .flat_map(MtuSearch::events)
+        .take_until(tokio::signal::ctrl_c())
+        .try_for_each(|discovery| {
+            println!("{}", format.discovery(&discovery));
+            ready(Ok(()))
+        })
+        .await
 }
 
-#[derive(Debug, From, Into, StructOpt)]
-struct Options {
-    #[structopt(parse(try_from_str = parse_arg))]
-    arg: Arg,
+/// Runs the traces one after the other, printing every hop.
+async fn route(traces: Vec<Trace>, format: Format) -> Result<(), Error> {
+    iter(traces)
+        .flat_map(Trace::events)
+        .take_until(tokio::signal::ctrl_c())
+        .try_for_each(|hop| {
+            println!("{}", format.hop(&hop));
+            ready(Ok(()))
+        })
+        .await
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Error> {
-    let (address, requests, interval): (Ipv4Addr, RequestsToSend, TransmissionInterval) =
-        Options::from_args().into();
-    let socket: IcmpSocket4 = "0.0.0.0".parse::<Ipv4Addr>()?.try_into()?;
-    iter(0..requests.into())
-        .map(Ok)
-        .try_fold(socket, |mut socket, sequence| {
-            tokio::time::sleep(Duration::from_millis(u16::from(interval).into()))
-                .then(move |_| async move {
-                    Icmpv4Packet::with_echo_request(5091, sequence, "test packet".as_bytes().to_vec())
-                        .map(|packet| {
-                            socket.set_timeout(Some(Duration::from_secs(5)));
-                            socket.send_to(address, packet)
-                        })
-                        .map(|_| (socket, Instant::now()))
-                })
-                .and_then(|(mut socket, send_time)| async move {
-                    tokio::select! {
-                        _ = tokio::time::sleep(Duration::from_secs(5)) => Ok(socket),
-                        Ok((Icmpv4Packet {
-                            code: _,
-                            typ: _,
-                            checksum: _,
-                            message: Icmpv4Message::EchoReply {
-                                identifier: _,
-                                sequence,
-                                payload: _
-                            }
-                        }, address)) = async { socket.rcv_from() } => {
-                            let elapsed = Instant::now() - send_time;
-                            let address = address.as_socket_ipv4().map(|sock| sock.ip().clone().to_string()).unwrap_or_default();
-                            println!("{},{:?},{:?}", address, sequence, elapsed.as_micros());
-                            Ok(socket)
-                        }
-                    }
-                })
+/// Pings until done or interrupted, printing every event and then the
+/// summaries, each followed by its histogram if asked for, and writing the
+/// histograms out.
+async fn ping(
+    pinger: Pinger,
+    format: Format,
+    summary_record: bool,
+    histogram: bool,
+    histogram_file: Option<PathBuf>,
+) -> Result<(), Error> {
+    let summaries = pinger
+        .targets()
+        .map(|(host, destination)| {
+            let summary = Summary::new(host.clone(), destination);
+            let summary = pinger
+                .source(destination)
+                .into_iter()
+                .fold(summary, Summary::with_source);
+            pinger
+                .interface()
+                .into_iter()
+                .fold(summary, Summary::with_interface)
         })
-        .await
-        .map(|_| ())
-        .map_err(Into::into)
+        .collect::<Vec<_>>();
+    let summaries = pinger
+        .events()
+        .take_until(tokio::signal::ctrl_c())
+        .try_fold(summaries, |mut summaries, event| {
+            if let Some(line) = format.event(&event) {
+                println!("{}", line);
+            }
+            summaries
+                .iter_mut()
+                .for_each(|summary| summary.record(&event));
+            ready(Ok(summaries))
+        })
+        .await?;
+    summaries.iter().for_each(|summary| {
+        match (format, summary_record) {
+            (Format::Csv, false) => println!("{}", summary),
+            (format, _) => println!("{}", format.summary(summary)),
+        }
+        if histogram && summary.received > 0 {
+            println!("{}", format.histogram(summary));
+        }
+    });
+    histogram_file.map_or(Ok(()), |path| {
+        let histograms = summaries
+            .iter()
+            .map(|summary| Format::Json.histogram(summary) + "\n")
+            .collect::<String>();
+        fs::write(path, histograms).map_err(Error::from)
+    })
 }
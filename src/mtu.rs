@@ -0,0 +1,411 @@
+use {
+    crate::{
+        rounds::Rounds, Arg, Error, Event, Host, Pattern, Payload, Pinger, Reason, Resolver,
+        SendFailure, Undelivered, MAX_PAYLOAD,
+    },
+    derive_more::From,
+    futures_util::{
+        future::ready,
+        stream::{try_unfold, Stream},
+        TryStreamExt,
+    },
+    std::{
+        fmt::{self, Display, Formatter},
+        net::IpAddr,
+        time::SystemTime,
+    },
+};
+
+/// How a probe of one payload size fared.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fit {
+    /// A reply came back.
+    Fits,
+    /// A router answered that the request needed fragmenting, giving its
+    /// next hop MTU where it could, or the kernel would not send it
+    /// unfragmented in the first place, in which case there is no responder.
+    TooBig {
+        mtu: Option<u32>,
+        responder: Option<IpAddr>,
+    },
+    /// Every request went unanswered, as happens when a router drops them
+    /// without a word.
+    Lost,
+}
+
+impl Display for Fit {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Fits => write!(formatter, "fits"),
+            Self::TooBig {
+                mtu,
+                responder: Some(responder),
+            } => write!(
+                formatter,
+                "{} from {}",
+                Reason::FragmentationNeeded { mtu: *mtu },
+                responder
+            ),
+            Self::TooBig {
+                responder: None, ..
+            } => write!(formatter, "too big"),
+            Self::Lost => write!(formatter, "lost"),
+        }
+    }
+}
+
+/// One step of the search: how requests with `size` payload bytes fared.
+#[derive(Clone, Debug)]
+pub struct MtuProbe {
+    pub destination: IpAddr,
+    pub size: usize,
+    pub fit: Fit,
+    /// Wall clock time at which the outcome was known.
+    pub timestamp: SystemTime,
+}
+
+impl MtuProbe {
+    /// The size of the IP packets that carried the probe.
+    pub fn packet(&self) -> usize {
+        self.size + overhead(self.destination)
+    }
+}
+
+/// Formats the probe as an `address,size,outcome` line.
+impl Display for MtuProbe {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+        write!(formatter, "{},{},{}", self.destination, self.size, self.fit)
+    }
+}
+
+/// The outcome of the search: the largest payload that made it through
+/// unfragmented, if even the smallest did.
+#[derive(Clone, Debug)]
+pub struct PathMtu {
+    pub host: Host,
+    pub destination: IpAddr,
+    pub size: Option<usize>,
+}
+
+impl PathMtu {
+    /// The path MTU, counting the IP and ICMP headers of the largest probe
+    /// that fit.
+    pub fn mtu(&self) -> Option<usize> {
+        self.size.map(|size| size + overhead(self.destination))
+    }
+}
+
+/// Formats the result as an `address,mtu,packet bytes,payload bytes` line,
+/// with both sizes left empty if nothing fit.
+impl Display for PathMtu {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+        let bytes = |size: Option<usize>| size.map(|size| size.to_string()).unwrap_or_default();
+        write!(
+            formatter,
+            "{},mtu,{},{}",
+            self.destination,
+            bytes(self.mtu()),
+            bytes(self.size)
+        )
+    }
+}
+
+/// What a [`MtuSearch`] yields: every probe, then the path MTU.
+#[derive(Clone, Debug, From)]
+pub enum Discovery {
+    Probe(MtuProbe),
+    Found(PathMtu),
+}
+
+/// Bytes of IP and ICMP header in front of the payload of an echo request.
+fn overhead(destination: IpAddr) -> usize {
+    match destination {
+        IpAddr::V4(_) => 20 + 8,
+        IpAddr::V6(_) => 40 + 8,
+    }
+}
+
+/// Finds the path MTU to a destination by binary searching the payload size
+/// of Don't Fragment echo requests between two bounds.
+///
+/// The search is made from a [`Pinger`] with one destination and a number of
+/// requests, which are all sent at every size unless one is answered first.
+/// A reply means the size fits. A fragmentation needed error means it does
+/// not, nor does anything above the MTU it reports, which is tried next; the
+/// kernel refusing to send the request means the same for the local link. No
+/// answer at all is taken to mean that a router dropped the requests
+/// silently, as happens on paths that black hole ICMP.
+pub struct MtuSearch {
+    rounds: Rounds,
+    min: usize,
+    max: usize,
+    pattern: Pattern,
+}
+
+impl TryFrom<Pinger> for MtuSearch {
+    type Error = Error;
+    fn try_from(pinger: Pinger) -> Result<Self, Self::Error> {
+        Rounds::new(pinger.with_dont_fragment(), "size").map(|rounds| Self {
+            rounds,
+            min: 0,
+            max: MAX_PAYLOAD,
+            pattern: Pattern::default(),
+        })
+    }
+}
+
+impl<'a> TryFrom<(Arg, &'a Resolver)> for MtuSearch {
+    type Error = Error;
+    fn try_from(arg: (Arg, &'a Resolver)) -> Result<Self, Self::Error> {
+        Pinger::try_from(arg)?.try_into()
+    }
+}
+
+impl MtuSearch {
+    /// Narrows the search to payloads from `min` to `max` bytes, which by
+    /// default is anything up to [`MAX_PAYLOAD`].
+    pub fn with_bounds(self, min: usize, max: usize) -> Result<Self, Error> {
+        match (min <= max, max <= MAX_PAYLOAD) {
+            (true, true) => Ok(Self { min, max, ..self }),
+            (false, _) => Err(format!("{} is larger than {}", min, max).into()),
+            (_, false) => Err(format!("payloads are limited to {} bytes", MAX_PAYLOAD).into()),
+        }
+    }
+
+    /// Overrides what the probes' payloads are filled with.
+    pub fn with_pattern(self, pattern: Pattern) -> Self {
+        Self { pattern, ..self }
+    }
+
+    /// Runs the search, yielding the outcome of every probe and then the
+    /// path MTU.
+    pub fn events(self) -> impl Stream<Item = Result<Discovery, Error>> {
+        let bounds = Bounds {
+            fits: None,
+            too_big: self.max + 1,
+            hint: None,
+        };
+        try_unfold(Some((self, bounds)), |state| async move {
+            let (mut search, mut bounds) = match state {
+                Some(state) => state,
+                None => return Ok(None),
+            };
+            match bounds.next(search.min, search.max) {
+                Some(size) => {
+                    let fit = search.probe(size).await?;
+                    let destination = search.rounds.destination;
+                    bounds.record(size, fit, overhead(destination));
+                    let probe = MtuProbe {
+                        destination,
+                        size,
+                        fit,
+                        timestamp: SystemTime::now(),
+                    };
+                    Ok(Some((probe.into(), Some((search, bounds)))))
+                }
+                None => {
+                    let found = PathMtu {
+                        host: search.rounds.host,
+                        destination: search.rounds.destination,
+                        size: bounds.fits,
+                    };
+                    Ok(Some((found.into(), None)))
+                }
+            }
+        })
+    }
+
+    /// Sends requests with `size` payload bytes until one is answered or
+    /// all of them are settled.
+    async fn probe(&mut self, size: usize) -> Result<Fit, Error> {
+        let payload = Payload::new(self.pattern.clone(), Some(size))?;
+        let events = self.rounds.round(|sized, _| *sized = payload);
+        let fit = Box::pin(events.try_filter_map(|event| {
+            ready(Ok(match event {
+                Event::Reply(_) => Some(Fit::Fits),
+                Event::Undelivered(Undelivered {
+                    reason: Reason::FragmentationNeeded { mtu },
+                    responder,
+                    ..
+                }) => Some(Fit::TooBig {
+                    mtu,
+                    responder: Some(responder),
+                }),
+                // The kernel knows the request cannot leave unfragmented.
+                Event::SendFailure(SendFailure { error, .. })
+                    if error.raw_os_error() == Some(libc::EMSGSIZE) =>
+                {
+                    Some(Fit::TooBig {
+                        mtu: None,
+                        responder: None,
+                    })
+                }
+                _ => None,
+            }))
+        }))
+        .try_next()
+        .await?;
+        Ok(fit.unwrap_or(Fit::Lost))
+    }
+}
+
+/// What the search has learnt so far.
+struct Bounds {
+    /// The largest payload known to fit.
+    fits: Option<usize>,
+    /// The smallest payload known not to.
+    too_big: usize,
+    /// A payload to try next, taken from a reported MTU.
+    hint: Option<usize>,
+}
+
+impl Bounds {
+    /// The next payload size to probe: the smallest, then the largest, then
+    /// a reported MTU's or the midpoint, until the two bounds meet.
+    fn next(&mut self, min: usize, max: usize) -> Option<usize> {
+        let hint = self.hint.take();
+        match self.fits {
+            None => Some(min).filter(|min| *min < self.too_big),
+            Some(fits) if self.too_big - fits <= 1 => None,
+            Some(fits) => match hint.filter(|hint| fits < *hint && *hint < self.too_big) {
+                Some(hint) => Some(hint),
+                None if self.too_big > max => Some(max),
+                None => Some(fits + (self.too_big - fits) / 2),
+            },
+        }
+    }
+
+    fn record(&mut self, size: usize, fit: Fit, overhead: usize) {
+        match fit {
+            Fit::Fits => self.fits = self.fits.max(Some(size)),
+            // Nothing larger than the reported MTU gets past the router
+            // that reported it either.
+            Fit::TooBig { mtu, .. } => {
+                self.hint = mtu
+                    .and_then(|mtu| (mtu as usize).checked_sub(overhead))
+                    .filter(|hint| *hint < size && Some(*hint) > self.fits);
+                self.too_big = self
+                    .too_big
+                    .min(size)
+                    .min(self.hint.map_or(usize::MAX, |hint| hint + 1));
+            }
+            Fit::Lost => self.too_big = self.too_big.min(size),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// IPv4 header and ICMP header bytes.
+    const OVERHEAD: usize = 28;
+
+    /// Runs the search against `path`, which says how every size fares,
+    /// yielding the sizes probed in order and the largest found to fit.
+    fn search(min: usize, max: usize, path: impl Fn(usize) -> Fit) -> (Vec<usize>, Option<usize>) {
+        let mut bounds = Bounds {
+            fits: None,
+            too_big: max + 1,
+            hint: None,
+        };
+        let mut probed = Vec::new();
+        while let Some(size) = bounds.next(min, max) {
+            assert!(!probed.contains(&size), "{} probed twice", size);
+            probed.push(size);
+            bounds.record(size, path(size), OVERHEAD);
+        }
+        (probed, bounds.fits)
+    }
+
+    /// A 1500 byte local link, then a router in front of a 1400 byte one
+    /// that reports its MTU.
+    fn reported(size: usize) -> Fit {
+        match size + OVERHEAD {
+            ..=1400 => Fit::Fits,
+            1401..=1500 => Fit::TooBig {
+                mtu: Some(1400),
+                responder: Some([10, 9, 0, 2].into()),
+            },
+            _ => Fit::TooBig {
+                mtu: None,
+                responder: None,
+            },
+        }
+    }
+
+    #[test]
+    fn follows_a_reported_mtu() {
+        let (probed, fits) = search(0, MAX_PAYLOAD, reported);
+        assert_eq!(
+            probed,
+            [0, 65507, 32753, 16376, 8188, 4094, 2047, 1023, 1535, 1279, 1407, 1372]
+        );
+        assert_eq!(fits, Some(1372));
+    }
+
+    #[test]
+    fn tries_a_reported_mtu_next() {
+        let (probed, fits) = search(0, 1450, reported);
+        assert_eq!(probed, [0, 1450, 1372]);
+        assert_eq!(fits, Some(1372));
+    }
+
+    #[test]
+    fn bisects_when_a_reported_mtu_is_wrong() {
+        // The router claims 1400 bytes, but only 1300 get through beyond it.
+        let path = |size: usize| match size + OVERHEAD {
+            ..=1300 => Fit::Fits,
+            1301..=1400 => Fit::Lost,
+            _ => reported(size),
+        };
+        let (probed, fits) = search(0, 1450, path);
+        assert_eq!(probed[..3], [0, 1450, 1372]);
+        assert_eq!(fits, Some(1272));
+    }
+
+    #[test]
+    fn ignores_a_reported_mtu_above_the_size_probed() {
+        let path = |size: usize| match size + OVERHEAD {
+            ..=1400 => Fit::Fits,
+            _ => Fit::TooBig {
+                mtu: Some(9000),
+                responder: Some([10, 9, 0, 2].into()),
+            },
+        };
+        let (probed, fits) = search(1000, 2000, path);
+        assert_eq!(probed[..3], [1000, 2000, 1500]);
+        assert_eq!(fits, Some(1372));
+    }
+
+    #[test]
+    fn bisects_a_black_hole() {
+        let path = |size: usize| match size + OVERHEAD {
+            ..=1400 => Fit::Fits,
+            _ => Fit::Lost,
+        };
+        let (probed, fits) = search(0, MAX_PAYLOAD, path);
+        assert_eq!(probed[..3], [0, 65507, 32753]);
+        assert!(probed.len() <= 18, "{} probes", probed.len());
+        assert_eq!(fits, Some(1372));
+    }
+
+    #[test]
+    fn stops_when_the_smallest_size_does_not_fit() {
+        assert_eq!(search(100, 2000, |_| Fit::Lost), (vec![100], None));
+        assert_eq!(search(1400, 2000, reported), (vec![1400], None));
+    }
+
+    #[test]
+    fn probes_once_when_the_bounds_meet() {
+        assert_eq!(search(1000, 1000, reported), (vec![1000], Some(1000)));
+        assert_eq!(search(2000, 2000, reported), (vec![2000], None));
+    }
+
+    #[test]
+    fn stops_when_everything_fits() {
+        let (probed, fits) = search(0, 9000, |_| Fit::Fits);
+        assert_eq!(probed, [0, 9000]);
+        assert_eq!(fits, Some(9000));
+    }
+}
@@ -0,0 +1,363 @@
+use {
+    crate::Error,
+    rand::RngCore,
+    std::{
+        fmt::{self, Display, Formatter},
+        fs,
+        str::FromStr,
+    },
+};
+
+/// The largest echo payload that fits in an IPv4 datagram.
+pub const MAX_PAYLOAD: usize = 65507;
+
+/// The payload every probe carried before it could be configured.
+const DEFAULT_FILL: &[u8] = b"test packet";
+
+/// What echo request payloads are filled with.
+#[derive(Clone, Debug)]
+pub enum Pattern {
+    /// These bytes, repeated as often as the size calls for.
+    Repeat(Vec<u8>),
+    /// Random bytes, drawn once per run.
+    Random,
+}
+
+impl Default for Pattern {
+    fn default() -> Self {
+        Self::Repeat(DEFAULT_FILL.to_vec())
+    }
+}
+
+/// Parses `random`, `hex:<digits>` or `file:<path>`.
+impl FromStr for Pattern {
+    type Err = Error;
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let bytes = match text.split_once(':') {
+            _ if text == "random" => return Ok(Self::Random),
+            Some(("hex", digits)) if digits.len() % 2 == 0 => (0..digits.len())
+                .step_by(2)
+                .map(|index| {
+                    digits
+                        .get(index..index + 2)
+                        .and_then(|pair| byte(pair, 16))
+                        .ok_or_else(|| Error::from(format!("{} is not hex", digits)))
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            Some(("hex", digits)) => {
+                return Err(format!("{} is an odd number of hex digits", digits).into())
+            }
+            Some(("file", path)) => fs::read(path)?,
+            _ => {
+                return Err(format!(
+                    "unknown pattern {}, expected random, hex:... or file:...",
+                    text
+                )
+                .into())
+            }
+        };
+        match bytes.is_empty() {
+            true => Err("a fill pattern needs at least one byte".to_string().into()),
+            false => Ok(Self::Repeat(bytes)),
+        }
+    }
+}
+
+/// Parses `digits` in `radix` as a byte, taking nothing but digits, where
+/// `u8::from_str_radix` would take a sign as well.
+pub(crate) fn byte(digits: &str, radix: u32) -> Option<u8> {
+    Some(digits)
+        .filter(|digits| digits.chars().all(|digit| digit.is_digit(radix)))
+        .and_then(|digits| u8::from_str_radix(digits, radix).ok())
+}
+
+/// Bytes at the front of a payload that identify the run and the probe that
+/// sent it, and when, so that a reply can be attributed and timed from its
+/// own contents.
+pub const STAMP_LENGTH: usize = 20;
+
+/// What goes into the first [`STAMP_LENGTH`] bytes of a payload, big endian.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Stamp {
+    /// Drawn at random for every run, so that stamps from other runs or
+    /// other tools are not mistaken for ours.
+    pub(crate) nonce: u64,
+    /// Nanoseconds from the start of the run to the send, on the monotonic
+    /// clock.
+    pub(crate) sent: u64,
+    pub(crate) target: u16,
+    pub(crate) sequence: u16,
+}
+
+impl Stamp {
+    fn write(&self, bytes: &mut [u8]) {
+        bytes[..8].copy_from_slice(&self.nonce.to_be_bytes());
+        bytes[8..16].copy_from_slice(&self.sent.to_be_bytes());
+        bytes[16..18].copy_from_slice(&self.target.to_be_bytes());
+        bytes[18..20].copy_from_slice(&self.sequence.to_be_bytes());
+    }
+
+    fn read(bytes: &[u8]) -> Option<Self> {
+        let field = |range: std::ops::Range<usize>| bytes.get(range);
+        Some(Self {
+            nonce: u64::from_be_bytes(field(0..8)?.try_into().ok()?),
+            sent: u64::from_be_bytes(field(8..16)?.try_into().ok()?),
+            target: u16::from_be_bytes(field(16..18)?.try_into().ok()?),
+            sequence: u16::from_be_bytes(field(18..20)?.try_into().ok()?),
+        })
+    }
+}
+
+/// The bytes carried by every echo request of a run: a [`Stamp`], unless
+/// the payload is too short for one, followed by the fill pattern.
+#[derive(Clone, Debug)]
+pub struct Payload {
+    bytes: Vec<u8>,
+    stamped: bool,
+}
+
+impl Default for Payload {
+    fn default() -> Self {
+        Self::filled(Pattern::default(), None)
+    }
+}
+
+impl Payload {
+    /// Fills `size` bytes, stamp included, with `pattern`. Without a size,
+    /// a repeated pattern is used once as it is after the stamp, and random
+    /// payloads are 56 bytes long.
+    pub fn new(pattern: Pattern, size: Option<usize>) -> Result<Self, Error> {
+        match (&pattern, size) {
+            (_, Some(size)) if size > MAX_PAYLOAD => {}
+            (Pattern::Repeat(bytes), None) if STAMP_LENGTH + bytes.len() > MAX_PAYLOAD => {}
+            _ => return Ok(Self::filled(pattern, size)),
+        }
+        Err(format!("payloads are limited to {} bytes", MAX_PAYLOAD).into())
+    }
+
+    fn filled(pattern: Pattern, size: Option<usize>) -> Self {
+        let size = size.unwrap_or(match &pattern {
+            Pattern::Repeat(bytes) => STAMP_LENGTH + bytes.len(),
+            Pattern::Random => 56,
+        });
+        let stamped = size >= STAMP_LENGTH;
+        let fill = match stamped {
+            true => size - STAMP_LENGTH,
+            false => size,
+        };
+        let fill: Vec<u8> = match pattern {
+            Pattern::Repeat(bytes) => bytes.iter().copied().cycle().take(fill).collect(),
+            Pattern::Random => {
+                let mut bytes = vec![0; fill];
+                rand::thread_rng().fill_bytes(&mut bytes);
+                bytes
+            }
+        };
+        let bytes = match stamped {
+            true => [vec![0; STAMP_LENGTH], fill].concat(),
+            false => fill,
+        };
+        Self { bytes, stamped }
+    }
+
+    /// The payload as it is sent, with a zeroed stamp if it has one.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// The payload to send, carrying `stamp` if there is room for one.
+    pub(crate) fn stamped(&self, stamp: &Stamp) -> Vec<u8> {
+        let mut bytes = self.bytes.clone();
+        if self.stamped {
+            stamp.write(&mut bytes);
+        }
+        bytes
+    }
+
+    /// The stamp an echoed payload carries, if this payload has one.
+    pub(crate) fn stamp_of(&self, echoed: &[u8]) -> Option<Stamp> {
+        self.stamped.then(|| Stamp::read(echoed)).flatten()
+    }
+
+    /// Compares an echoed payload against the one that was sent, apart from
+    /// the stamp, which differs from probe to probe.
+    pub(crate) fn check(&self, echoed: &[u8]) -> Integrity {
+        let skip = match self.stamped {
+            true => STAMP_LENGTH.min(echoed.len()),
+            false => 0,
+        };
+        let mismatch = self.bytes[skip..]
+            .iter()
+            .zip(&echoed[skip..])
+            .position(|(sent, echoed)| sent != echoed);
+        match mismatch {
+            Some(offset) => Integrity::Corrupted {
+                offset: skip + offset,
+            },
+            None if echoed.len() < self.bytes.len() => Integrity::Truncated {
+                length: echoed.len(),
+            },
+            None if echoed.len() > self.bytes.len() => Integrity::Corrupted {
+                offset: self.bytes.len(),
+            },
+            None => Integrity::Intact,
+        }
+    }
+}
+
+/// Whether a reply echoed the request's payload unchanged.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Integrity {
+    Intact,
+    /// Only the first `length` bytes came back, unchanged.
+    Truncated {
+        length: usize,
+    },
+    /// The echoed payload first differs from the sent one at `offset`, or
+    /// has extra bytes from there on.
+    Corrupted {
+        offset: usize,
+    },
+}
+
+impl Display for Integrity {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Intact => write!(formatter, "intact"),
+            Self::Truncated { .. } => write!(formatter, "truncated"),
+            Self::Corrupted { .. } => write!(formatter, "corrupted"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STAMP: Stamp = Stamp {
+        nonce: 0x0123_4567_89ab_cdef,
+        sent: 42,
+        target: 1,
+        sequence: 7,
+    };
+
+    fn repeat(fill: &[u8], size: usize) -> Payload {
+        Payload::new(Pattern::Repeat(fill.to_vec()), Some(size)).unwrap()
+    }
+
+    #[test]
+    fn intact_payloads_check_out() {
+        let payload = Payload::default();
+        let echoed = payload.stamped(&STAMP);
+        assert_eq!(echoed.len(), STAMP_LENGTH + 11);
+        assert_eq!(&echoed[STAMP_LENGTH..], b"test packet");
+        assert_eq!(payload.stamp_of(&echoed), Some(STAMP));
+        assert_eq!(payload.check(&echoed), Integrity::Intact);
+    }
+
+    #[test]
+    fn stamps_are_not_compared() {
+        let payload = Payload::default();
+        let mut echoed = payload.stamped(&STAMP);
+        echoed[..STAMP_LENGTH].fill(0xff);
+        assert_eq!(payload.check(&echoed), Integrity::Intact);
+    }
+
+    #[test]
+    fn truncated_payloads_keep_their_length() {
+        let payload = repeat(b"ab", 64);
+        let echoed = payload.stamped(&STAMP);
+        assert_eq!(
+            payload.check(&echoed[..40]),
+            Integrity::Truncated { length: 40 }
+        );
+        assert_eq!(
+            payload.check(&echoed[..10]),
+            Integrity::Truncated { length: 10 }
+        );
+        assert_eq!(payload.check(&[]), Integrity::Truncated { length: 0 });
+    }
+
+    #[test]
+    fn corruption_is_located() {
+        let payload = repeat(b"ab", 64);
+        let mut echoed = payload.stamped(&STAMP);
+        echoed[33] ^= 1;
+        echoed[50] ^= 1;
+        assert_eq!(payload.check(&echoed), Integrity::Corrupted { offset: 33 });
+        assert_eq!(
+            payload.check(&echoed[..33]),
+            Integrity::Truncated { length: 33 }
+        );
+    }
+
+    #[test]
+    fn extra_bytes_are_corruption() {
+        let payload = repeat(b"ab", 64);
+        let echoed = [payload.stamped(&STAMP), b"xy".to_vec()].concat();
+        assert_eq!(payload.check(&echoed), Integrity::Corrupted { offset: 64 });
+    }
+
+    #[test]
+    fn short_payloads_are_unstamped() {
+        let payload = repeat(b"ab", STAMP_LENGTH - 1);
+        let echoed = payload.stamped(&STAMP);
+        assert_eq!(echoed, b"abababababababababa");
+        assert_eq!(payload.stamp_of(&echoed), None);
+        assert_eq!(payload.check(&echoed), Integrity::Intact);
+        assert_eq!(
+            payload.check(b"Xbababababababababa"),
+            Integrity::Corrupted { offset: 0 }
+        );
+        assert_eq!(payload.check(b"abab"), Integrity::Truncated { length: 4 });
+        assert_eq!(repeat(b"ab", 0).check(&[]), Integrity::Intact);
+        assert!(repeat(b"ab", STAMP_LENGTH).stamp_of(&[0; 20]).is_some());
+    }
+
+    #[test]
+    fn oversized_payloads_are_refused() {
+        assert!(Payload::new(Pattern::Random, Some(MAX_PAYLOAD)).is_ok());
+        assert!(Payload::new(Pattern::Random, Some(MAX_PAYLOAD + 1)).is_err());
+        assert!(Payload::new(Pattern::Repeat(vec![0; MAX_PAYLOAD]), None).is_err());
+    }
+
+    #[test]
+    fn patterns_parse() {
+        let bytes = |text: &str| match text.parse::<Pattern>() {
+            Ok(Pattern::Repeat(bytes)) => Some(bytes),
+            _ => None,
+        };
+        assert_eq!(bytes("hex:00ff7A"), Some(vec![0x00, 0xff, 0x7a]));
+        assert!(matches!("random".parse(), Ok(Pattern::Random)));
+    }
+
+    #[test]
+    fn bad_patterns_are_refused() {
+        [
+            "hex:abc",
+            "hex:zz",
+            "hex:",
+            "hex:+1",
+            "hex:aéa",
+            "hex:éé",
+            "file:/nonexistent/pattern",
+            "bytes:00",
+            "Random",
+            "",
+        ]
+        .into_iter()
+        .for_each(|text| assert!(text.parse::<Pattern>().is_err(), "{}", text));
+    }
+
+    #[test]
+    fn refusals_say_why() {
+        let error = |text: &str| text.parse::<Pattern>().unwrap_err().to_string();
+        assert_eq!(error("hex:abc"), "abc is an odd number of hex digits");
+        assert_eq!(error("hex:zz"), "zz is not hex");
+        assert_eq!(error("hex:"), "a fill pattern needs at least one byte");
+        assert_eq!(
+            error("bytes:00"),
+            "unknown pattern bytes:00, expected random, hex:... or file:..."
+        );
+    }
+}
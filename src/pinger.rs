@@ -0,0 +1,1027 @@
+use {
+    crate::{
+        payload::Stamp,
+        socket::{Datagram, Marking, Received, Socket},
+        Arg, Clock, Error, Event, Host, Jitter, Payload, Reason, Reply, RequestsToSend, Resolver,
+        SendFailure, Timeout, Tos, Undelivered,
+    },
+    futures_util::{
+        future::{ready, select_all},
+        stream::{iter, once, try_unfold, Stream},
+        FutureExt, TryStreamExt,
+    },
+    std::{
+        borrow::BorrowMut,
+        collections::{HashMap, VecDeque},
+        net::IpAddr,
+        sync::Arc,
+        time::{Duration, Instant, SystemTime},
+    },
+};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// One destination's share of a run.
+struct Target {
+    host: Host,
+    destination: IpAddr,
+    requests: RequestsToSend,
+    interval: Duration,
+    sent: u64,
+    /// When the next request is scheduled, or `None` once all have been sent.
+    next_send: Option<Instant>,
+    /// The variation of the round trip times of every reply so far, which is
+    /// what replies and summaries report.
+    variation: Jitter,
+}
+
+/// An echo request on the wire. Replies are timed from the send time kept
+/// here, and from the stamp in their payload once it has been forgotten; the
+/// probe is also kept for its deadline and to tell a first reply from copies
+/// of it.
+struct Probe {
+    target: usize,
+    sequence: u16,
+    send_time: Instant,
+    /// What took the send time: user space as the request was handed to the
+    /// kernel, until the kernel or network card report when it left.
+    send_clock: Clock,
+    deadline: Instant,
+    timed_out: bool,
+    /// Whether a reply or an ICMP error has settled the probe, after which
+    /// any more of them are duplicates.
+    answered: bool,
+}
+
+impl Probe {
+    /// Whether the probe still counts as in flight.
+    fn pending(&self) -> bool {
+        !self.timed_out && !self.answered
+    }
+}
+
+/// Sends echo requests to one or more destinations, each with its own
+/// request count and interval.
+///
+/// Requests go out on a fixed schedule, one per interval from the start of
+/// the run, however long replies take; any number of them may be in flight.
+///
+/// Every destination must resolve to an address of its own. All of them
+/// share one socket per address family and one identifier.
+/// Requests are numbered on the wire from a single counter, so replies are
+/// told apart by (identifier, sequence) alone and then reported under the
+/// sequence number of their own destination.
+///
+/// Every payload starts with a stamp holding a nonce drawn for the run, the
+/// send time and the probe's own destination and sequence, so a reply can be
+/// attributed and timed from its contents even after its bookkeeping has
+/// been forgotten, as happens once the wire sequence numbers wrap around.
+///
+/// Construction resolves the destinations and opens the ICMP sockets, so
+/// unknown names and permission problems surface from `Pinger::try_from`
+/// rather than from the first probe. Raw sockets are used where the process
+/// may open them, and unprivileged ping sockets otherwise. The sockets are
+/// registered with the Tokio reactor, so construction must happen within a
+/// Tokio runtime.
+pub struct Pinger {
+    targets: Vec<Target>,
+    identifier: u16,
+    timeout: Duration,
+    payload: Payload,
+    marking: Marking,
+    /// Whether replies carry their destination's jitter.
+    report_jitter: bool,
+    sockets: Vec<Socket>,
+    /// Tells this run's stamps apart from those of any other.
+    nonce: u64,
+    /// What stamped send times count from.
+    epoch: Instant,
+    next_sequence: u16,
+    /// Requests that have neither been answered nor timed out.
+    in_flight: usize,
+    /// Requests sent, keyed by (identifier, wire sequence). Answered and
+    /// timed out requests stay here until the key is reused, so that late
+    /// replies are reported and copies of a reply are known for duplicates.
+    outstanding: HashMap<(u16, u16), Probe>,
+    /// Keys of `outstanding` in the order they time out.
+    deadlines: VecDeque<(Instant, (u16, u16))>,
+}
+
+impl TryFrom<Arg> for Pinger {
+    type Error = Error;
+    fn try_from(arg: Arg) -> Result<Self, Self::Error> {
+        (vec![arg], &Resolver::default()).try_into()
+    }
+}
+
+impl<'a> TryFrom<(Arg, &'a Resolver)> for Pinger {
+    type Error = Error;
+    fn try_from((arg, resolver): (Arg, &'a Resolver)) -> Result<Self, Self::Error> {
+        (vec![arg], resolver).try_into()
+    }
+}
+
+impl<'a> TryFrom<(Vec<Arg>, &'a Resolver)> for Pinger {
+    type Error = Error;
+    fn try_from((args, resolver): (Vec<Arg>, &'a Resolver)) -> Result<Self, Self::Error> {
+        let targets = args
+            .into_iter()
+            .map(|arg| {
+                let (host, requests, interval) = arg.into();
+                resolver.resolve(&host).map(|destination| Target {
+                    host,
+                    destination,
+                    requests,
+                    interval: interval.into(),
+                    sent: 0,
+                    next_send: None,
+                    variation: Jitter::default(),
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        // Events and summaries go by destination address, so two of them
+        // for one address could not be told apart.
+        if let Some((first, second)) = targets.iter().enumerate().find_map(|(index, second)| {
+            targets[..index]
+                .iter()
+                .find(|first| first.destination == second.destination)
+                .map(|first| (first, second))
+        }) {
+            return Err(match (&first.host, &second.host) {
+                (Host::Address(_), Host::Address(_)) => {
+                    format!("{} is given more than once", second.destination)
+                }
+                _ => format!(
+                    "{} and {} both resolve to {}, give every destination once",
+                    first.host, second.host, second.destination
+                ),
+            }
+            .into());
+        }
+        let sockets = [
+            targets.iter().find(|target| target.destination.is_ipv4()),
+            targets.iter().find(|target| target.destination.is_ipv6()),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|target| Socket::try_from(target.destination))
+        .collect::<Result<Vec<_>, _>>()?;
+        match targets.is_empty() {
+            true => Err("at least one destination is required".to_string().into()),
+            false => Ok(Self {
+                targets,
+                identifier: std::process::id() as u16,
+                timeout: DEFAULT_TIMEOUT,
+                payload: Payload::default(),
+                marking: Marking::default(),
+                report_jitter: false,
+                sockets,
+                nonce: rand::random(),
+                epoch: Instant::now(),
+                next_sequence: 0,
+                in_flight: 0,
+                outstanding: HashMap::new(),
+                deadlines: VecDeque::new(),
+            }),
+        }
+    }
+}
+
+impl Pinger {
+    /// Overrides the echo identifier, which defaults to the low 16 bits of the
+    /// process id so that concurrent runs ignore each other's replies.
+    pub fn with_identifier(self, identifier: u16) -> Self {
+        Self { identifier, ..self }
+    }
+
+    /// Overrides how long each probe waits for its reply, five seconds by default.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Self { timeout, ..self }
+    }
+
+    /// Overrides the payload of every request, a stamp followed by the 11
+    /// bytes of `test packet` by default. Replies are checked against it.
+    pub fn with_payload(self, payload: Payload) -> Self {
+        Self { payload, ..self }
+    }
+
+    /// Sets the IPv4 time to live and IPv6 hop limit of every request, so
+    /// that probes travelling further are answered with a time exceeded
+    /// error from the router where they ran out.
+    pub fn with_ttl(self, ttl: u8) -> Self {
+        let marking = Marking {
+            ttl: Some(ttl),
+            ..self.marking
+        };
+        Self { marking, ..self }
+    }
+
+    /// Marks every request with this IPv4 type of service or IPv6 traffic
+    /// class. Replies report the marking they came back with, so that
+    /// remarking along the path shows.
+    pub fn with_tos(self, tos: Tos) -> Self {
+        let marking = Marking {
+            tos: Some(tos.into()),
+            ..self.marking
+        };
+        Self { marking, ..self }
+    }
+
+    /// Sets the Don't Fragment bit on IPv4 requests and keeps IPv6 requests
+    /// from being fragmented locally, so that requests too large for the
+    /// path are answered with a fragmentation needed error or dropped.
+    pub fn with_dont_fragment(self) -> Self {
+        let marking = Marking {
+            dont_fragment: true,
+            ..self.marking
+        };
+        Self { marking, ..self }
+    }
+
+    /// Has every reply carry the RFC 3550 jitter of its destination's replies
+    /// so far.
+    pub fn with_jitter(self) -> Self {
+        Self {
+            report_jitter: true,
+            ..self
+        }
+    }
+
+    /// Sends the requests of `source`'s address family from `source`, which
+    /// must be one of this host's addresses. Destinations of the other family
+    /// are unaffected.
+    pub fn with_source(self, source: IpAddr) -> Result<Self, Error> {
+        self.sockets
+            .iter()
+            .find(|socket| socket.serves(&source))
+            .ok_or_else(|| Error::from(format!("no destination to send from {} to", source)))?
+            .bind(source)
+            .map_err(|error| format!("cannot send from {}: {}", source, error))?;
+        Ok(self)
+    }
+
+    /// Sends every request through `interface`, whatever the routing table
+    /// says, and only listens for replies arriving on it.
+    pub fn with_interface(self, interface: &str) -> Result<Self, Error> {
+        self.sockets
+            .iter()
+            .try_for_each(|socket| socket.bind_device(interface))
+            .map_err(|error| format!("cannot send through {}: {}", interface, error))?;
+        Ok(self)
+    }
+
+    /// The address requests to `destination` are sent from, if one was set
+    /// with [`with_source`](Self::with_source).
+    pub fn source(&self, destination: IpAddr) -> Option<IpAddr> {
+        self.sockets
+            .iter()
+            .find(|socket| socket.serves(&destination))
+            .and_then(Socket::source)
+    }
+
+    /// The interface requests are sent through, if one was set with
+    /// [`with_interface`](Self::with_interface).
+    pub fn interface(&self) -> Option<String> {
+        self.sockets.iter().find_map(Socket::device)
+    }
+
+    /// Every destination as it was given, along with the address it resolved to.
+    pub fn targets(&self) -> impl Iterator<Item = (&Host, IpAddr)> {
+        self.targets
+            .iter()
+            .map(|target| (&target.host, target.destination))
+    }
+
+    /// Every destination's request count, in the order of
+    /// [`targets`](Self::targets).
+    pub(crate) fn requests(&self) -> impl Iterator<Item = RequestsToSend> + '_ {
+        self.targets.iter().map(|target| target.requests)
+    }
+
+    /// Runs every destination's probes side by side, yielding what happens to
+    /// each of them.
+    ///
+    /// Every probe that sees no reply within the timeout yields a
+    /// [`Timeout`], and every probe answered with an ICMP error quoting it,
+    /// such as a time exceeded from a router, an [`Undelivered`]. Late
+    /// replies are still yielded with their own round trip time, and any
+    /// reply or error after the first for the same probe is yielded flagged
+    /// as a duplicate; replies carrying another identifier, such as those
+    /// meant for a concurrent run, are dropped. The stream ends once the last
+    /// request has been answered or has timed out; a continuous pinger never
+    /// ends, so drop the stream to stop it.
+    pub fn events(mut self) -> impl Stream<Item = Result<Event, Error>> {
+        let started = self.start();
+        run(self, started)
+    }
+
+    /// Runs every destination's probes once more, the way
+    /// [`events`](Self::events) does, over the same sockets and under the
+    /// same identifier, after `configure` has changed the payload or marking
+    /// they are sent with. Whatever comes back for earlier runs is dropped
+    /// from now on, so an earlier stream can be given up halfway.
+    pub(crate) fn again(
+        &mut self,
+        configure: impl FnOnce(&mut Payload, &mut Marking),
+    ) -> impl Stream<Item = Result<Event, Error>> + '_ {
+        configure(&mut self.payload, &mut self.marking);
+        self.nonce = rand::random();
+        self.in_flight = 0;
+        self.outstanding.clear();
+        self.deadlines.clear();
+        self.targets.iter_mut().for_each(|target| {
+            target.sent = 0;
+            target.variation = Jitter::default();
+        });
+        let started = self.start();
+        run(self, started)
+    }
+
+    /// Schedules every destination's first request and marks the sockets.
+    fn start(&mut self) -> Result<(), Error> {
+        let now = Instant::now();
+        self.targets
+            .iter_mut()
+            .for_each(|target| target.next_send = Some(now + target.interval));
+        // Marking the sockets once, rather than before every request, keeps
+        // system calls out from between a request's send time and its send.
+        self.sockets
+            .iter()
+            .try_for_each(|socket| socket.mark(self.marking))
+            .map_err(Error::from)
+    }
+
+    /// Handles whatever is due next: timeouts, then requests, then replies.
+    /// Yields `None` once every request has been sent and settled.
+    async fn step(&mut self) -> Result<Option<Vec<Event>>, Error> {
+        let now = Instant::now();
+        let mut events = self.expire(now);
+        events.extend(self.send_due(now).await?);
+        if !events.is_empty() {
+            return Ok(Some(events));
+        }
+        let wake = self
+            .targets
+            .iter()
+            .filter_map(|target| target.next_send)
+            .chain(self.deadlines.front().map(|(deadline, _)| *deadline))
+            .min();
+        match (wake, self.finished()) {
+            (_, true) | (None, _) => Ok(None),
+            (Some(wake), false) => self.receive(wake).await.map(Some),
+        }
+    }
+
+    fn finished(&self) -> bool {
+        self.in_flight == 0 && self.targets.iter().all(|target| target.next_send.is_none())
+    }
+
+    /// Reports the requests whose deadline has passed without a reply.
+    fn expire(&mut self, now: Instant) -> Vec<Event> {
+        let mut events = Vec::new();
+        while let Some(&(deadline, key)) = self.deadlines.front() {
+            if deadline > now {
+                break;
+            }
+            self.deadlines.pop_front();
+            // The key may have been answered, or reused once the wire
+            // sequence numbers wrapped around.
+            if let Some(probe) = self.outstanding.get_mut(&key) {
+                if probe.deadline == deadline && probe.pending() {
+                    probe.timed_out = true;
+                    self.in_flight -= 1;
+                    events.push(Event::Timeout(Timeout {
+                        destination: self.targets[probe.target].destination,
+                        sequence: probe.sequence,
+                        timestamp: SystemTime::now(),
+                    }));
+                }
+            }
+        }
+        events
+    }
+
+    /// Sends the next request of every destination that is due one.
+    async fn send_due(&mut self, now: Instant) -> Result<Vec<Event>, Error> {
+        let mut events = Vec::new();
+        for index in 0..self.targets.len() {
+            let target = &self.targets[index];
+            let scheduled = match target.next_send {
+                Some(scheduled) if scheduled <= now => scheduled,
+                _ => continue,
+            };
+            let (destination, sequence) = (target.destination, target.sent as u16);
+            let wire_sequence = self.next_sequence;
+            let identifier = self.identifier;
+            // A little ahead of the send time proper, which is only known once
+            // the stamp has gone out with the request.
+            let payload = self.payload.stamped(&Stamp {
+                nonce: self.nonce,
+                sent: self.epoch.elapsed().as_nanos() as u64,
+                target: index as u16,
+                sequence,
+            });
+            let sent = self
+                .sockets
+                .iter_mut()
+                .find(|socket| socket.serves(&destination))
+                .ok_or_else(|| Error::from(format!("no socket for {}", destination)))?
+                .send_echo(destination, identifier, wire_sequence, payload)
+                .await?;
+            self.next_sequence = wire_sequence.wrapping_add(1);
+            let target = &mut self.targets[index];
+            target.sent += 1;
+            // Scheduling from the previous slot rather than from the actual
+            // send time keeps the rate from drifting.
+            target.next_send = match target.requests.exhausted_by(target.sent) {
+                true => None,
+                false => Some(scheduled + target.interval),
+            };
+            // A request that never left has nothing to wait for.
+            let send_time = match sent {
+                Ok(send_time) => send_time,
+                Err(error) => {
+                    events.push(Event::SendFailure(SendFailure {
+                        destination,
+                        sequence,
+                        error: Arc::new(error),
+                        timestamp: SystemTime::now(),
+                    }));
+                    continue;
+                }
+            };
+            self.track(index, sequence, (identifier, wire_sequence), send_time);
+            events.push(Event::Sent {
+                destination,
+                sequence,
+            });
+        }
+        Ok(events)
+    }
+
+    /// Keeps track of a request of `target`'s that went out under `key`, to
+    /// wait for its reply until its deadline.
+    fn track(&mut self, target: usize, sequence: u16, key: (u16, u16), send_time: Instant) {
+        let deadline = send_time + self.timeout;
+        // A request still outstanding under this key after the wire
+        // sequence numbers wrapped around is forgotten.
+        let replaced = self.outstanding.insert(
+            key,
+            Probe {
+                target,
+                sequence,
+                send_time,
+                send_clock: Clock::User,
+                deadline,
+                timed_out: false,
+                answered: false,
+            },
+        );
+        if replaced.is_some_and(|probe| probe.pending()) {
+            self.in_flight -= 1;
+        }
+        self.deadlines.push_back((deadline, key));
+        self.in_flight += 1;
+    }
+
+    /// Listens for a reply on every socket until `wake`.
+    async fn receive(&mut self, wake: Instant) -> Result<Vec<Event>, Error> {
+        let received = tokio::select! {
+            _ = tokio::time::sleep_until(wake.into()) => None,
+            (received, index, _) = select_all(
+                self.sockets.iter_mut().map(|socket| socket.rcv_from().boxed()),
+            ) => Some((received?, index)),
+        };
+        Ok(received
+            .and_then(|(mut datagram, index)| {
+                // Ping sockets only hand back replies and errors about their
+                // own requests, under the identifier the kernel gave them.
+                if let (
+                    true,
+                    Received::EchoReply { identifier, .. } | Received::Error { identifier, .. },
+                ) = (
+                    self.sockets[index].rewrites_identifier(),
+                    &mut datagram.message,
+                ) {
+                    *identifier = self.identifier;
+                }
+                self.attribute(datagram)
+            })
+            .into_iter()
+            .collect())
+    }
+
+    /// Matches an incoming reply or ICMP error against the outstanding
+    /// probes, settling the probe it answers, or flagging it as a duplicate
+    /// if that probe was settled before. A message carrying a stamp of this
+    /// run is reported even when its probe has been forgotten, and is then
+    /// timed by its stamp.
+    fn attribute(&mut self, datagram: Datagram) -> Option<Event> {
+        // The kernel or network card can tell when a request left better
+        // than user space, unless its reply came back first.
+        if let Received::Sent {
+            identifier,
+            sequence,
+        } = datagram.message
+        {
+            if let Some(probe) = self
+                .outstanding
+                .get_mut(&(identifier, sequence))
+                .filter(|probe| !probe.answered && probe.send_clock < datagram.clock)
+            {
+                probe.send_time = datagram.arrival;
+                probe.send_clock = datagram.clock;
+            }
+            return None;
+        }
+        let responder = datagram.source?;
+        let (identifier, sequence, payload) = match &datagram.message {
+            Received::EchoReply {
+                identifier,
+                sequence,
+                payload,
+                ..
+            }
+            | Received::Error {
+                identifier,
+                sequence,
+                payload,
+                ..
+            } => (*identifier, *sequence, payload),
+            Received::Sent { .. } | Received::Other => return None,
+        };
+        // A stamp from another run, such as an earlier pinger with the same
+        // identifier, means the message is not ours even if its key is.
+        let stamp = match self.payload.stamp_of(payload) {
+            Some(stamp)
+                if stamp.nonce != self.nonce || usize::from(stamp.target) >= self.targets.len() =>
+            {
+                return None
+            }
+            stamp => stamp,
+        };
+        let key = (identifier, sequence);
+        let answers = |probe: &Probe| {
+            stamp.is_none_or(|stamp| {
+                usize::from(stamp.target) == probe.target && stamp.sequence == probe.sequence
+            })
+        };
+        // A redirected request still went on, so its reply or timeout is yet
+        // to come.
+        let settles = !matches!(
+            datagram.message,
+            Received::Error {
+                reason: Reason::Redirect { .. },
+                ..
+            }
+        );
+        let probe = match self
+            .outstanding
+            .get_mut(&key)
+            .filter(|probe| answers(probe))
+        {
+            Some(probe) => {
+                let duplicate = probe.answered;
+                if settles && probe.pending() {
+                    self.in_flight -= 1;
+                }
+                probe.answered |= settles;
+                Some((
+                    probe.target,
+                    probe.sequence,
+                    probe.send_time,
+                    probe.send_clock,
+                    duplicate,
+                ))
+            }
+            None => None,
+        };
+        let duplicate = probe.is_some_and(|(.., duplicate)| duplicate);
+        let (target, sequence, send_time, send_clock) = match (probe, stamp) {
+            (Some((target, sequence, send_time, send_clock, _)), _) => {
+                (target, sequence, send_time, send_clock)
+            }
+            (None, Some(stamp)) => (
+                usize::from(stamp.target),
+                stamp.sequence,
+                self.epoch + Duration::from_nanos(stamp.sent),
+                Clock::User,
+            ),
+            (None, None) => return None,
+        };
+        let (destination, round_trip) = (
+            self.targets[target].destination,
+            datagram.arrival.saturating_duration_since(send_time),
+        );
+        match datagram.message {
+            Received::EchoReply {
+                ttl, tos, payload, ..
+            } => Some(Event::Reply(Reply {
+                destination,
+                responder,
+                sequence,
+                round_trip,
+                variation: {
+                    let variation = &mut self.targets[target].variation;
+                    if !duplicate {
+                        variation.record(round_trip);
+                    }
+                    *variation
+                },
+                jitter: self.targets[target]
+                    .variation
+                    .smoothed()
+                    .filter(|_| self.report_jitter),
+                ttl,
+                sent_tos: self.marking.tos.map(Tos::from),
+                reply_tos: tos.map(Tos::from),
+                integrity: self.payload.check(&payload),
+                sent_clock: send_clock,
+                clock: datagram.clock,
+                duplicate,
+                timestamp: SystemTime::now(),
+            })),
+            Received::Error { reason, .. } => Some(Event::Undelivered(Undelivered {
+                destination,
+                responder,
+                sequence,
+                round_trip,
+                reason,
+                duplicate,
+                timestamp: SystemTime::now(),
+            })),
+            Received::Sent { .. } | Received::Other => None,
+        }
+    }
+}
+
+/// Steps `pinger` until its run is over, or yields why it could not start.
+fn run<P: BorrowMut<Pinger>>(
+    pinger: P,
+    started: Result<(), Error>,
+) -> impl Stream<Item = Result<Event, Error>> {
+    let events = started.map(|()| {
+        try_unfold(pinger, |mut pinger| async move {
+            pinger
+                .borrow_mut()
+                .step()
+                .await
+                .map(|events| events.map(|events| (iter(events.into_iter().map(Ok)), pinger)))
+        })
+        .try_flatten()
+    });
+    once(ready(events)).try_flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{Pattern, STAMP_LENGTH},
+        std::net::Ipv4Addr,
+    };
+
+    const IDENTIFIER: u16 = 0x1234;
+    const NONCE: u64 = 0x0123_4567_89ab_cdef;
+    const ROUTER: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 9, 0, 2));
+
+    /// A pinger for `destinations` that has sent nothing yet, without
+    /// sockets, which attribution does not need.
+    fn pinger(destinations: &[&str]) -> Pinger {
+        Pinger {
+            targets: destinations
+                .iter()
+                .map(|destination| Target {
+                    host: Host::from(*destination),
+                    destination: destination.parse().unwrap(),
+                    requests: RequestsToSend::Count(10),
+                    interval: Duration::from_millis(100),
+                    sent: 0,
+                    next_send: None,
+                    variation: Jitter::default(),
+                })
+                .collect(),
+            identifier: IDENTIFIER,
+            timeout: DEFAULT_TIMEOUT,
+            payload: Payload::default(),
+            marking: Marking::default(),
+            report_jitter: false,
+            sockets: Vec::new(),
+            nonce: NONCE,
+            epoch: Instant::now(),
+            next_sequence: 0,
+            in_flight: 0,
+            outstanding: HashMap::new(),
+            deadlines: VecDeque::new(),
+        }
+    }
+
+    /// What a probe of `target`'s with `sequence`, stamped as sent at
+    /// `sent` into the run, would echo back.
+    fn echoed(pinger: &Pinger, nonce: u64, target: u16, sequence: u16, sent: Duration) -> Vec<u8> {
+        pinger.payload.stamped(&Stamp {
+            nonce,
+            sent: sent.as_nanos() as u64,
+            target,
+            sequence,
+        })
+    }
+
+    fn reply(key: (u16, u16), payload: Vec<u8>, arrival: Instant) -> Datagram {
+        Datagram {
+            message: Received::EchoReply {
+                identifier: key.0,
+                sequence: key.1,
+                ttl: Some(63),
+                tos: None,
+                payload,
+            },
+            source: Some(ROUTER),
+            arrival,
+            clock: Clock::Kernel,
+        }
+    }
+
+    fn error(reason: Reason, key: (u16, u16), payload: Vec<u8>, arrival: Instant) -> Datagram {
+        Datagram {
+            message: Received::Error {
+                reason,
+                identifier: key.0,
+                sequence: key.1,
+                payload,
+            },
+            source: Some(ROUTER),
+            arrival,
+            clock: Clock::Kernel,
+        }
+    }
+
+    /// The destination, sequence, round trip and duplicate flag of a reply.
+    fn replied(event: Option<Event>) -> (IpAddr, u16, Duration, bool) {
+        match event {
+            Some(Event::Reply(reply)) => (
+                reply.destination,
+                reply.sequence,
+                reply.round_trip,
+                reply.duplicate,
+            ),
+            event => panic!("expected a reply, got {:?}", event),
+        }
+    }
+
+    /// The sequence, reason and duplicate flag of an ICMP error.
+    fn undelivered(event: Option<Event>) -> (u16, Reason, bool) {
+        match event {
+            Some(Event::Undelivered(undelivered)) => (
+                undelivered.sequence,
+                undelivered.reason,
+                undelivered.duplicate,
+            ),
+            event => panic!("expected an ICMP error, got {:?}", event),
+        }
+    }
+
+    #[test]
+    fn replies_are_matched_by_identifier_and_sequence() {
+        let mut pinger = pinger(&["10.9.1.2", "10.9.0.2"]);
+        let sent = pinger.epoch + Duration::from_millis(1);
+        pinger.track(0, 0, (IDENTIFIER, 0), sent);
+        pinger.track(1, 0, (IDENTIFIER, 1), sent);
+        let payload = echoed(&pinger, NONCE, 1, 0, Duration::from_millis(1));
+        let event = pinger.attribute(reply(
+            (IDENTIFIER, 1),
+            payload,
+            sent + Duration::from_micros(250),
+        ));
+        assert_eq!(
+            replied(event),
+            (
+                "10.9.0.2".parse().unwrap(),
+                0,
+                Duration::from_micros(250),
+                false
+            )
+        );
+        assert_eq!(pinger.in_flight, 1);
+    }
+
+    #[test]
+    fn foreign_identifiers_and_nonces_are_dropped() {
+        let mut pinger = pinger(&["10.9.1.2"]);
+        let sent = pinger.epoch;
+        pinger.track(0, 0, (IDENTIFIER, 0), sent);
+        let stamped = |nonce| echoed(&pinger, nonce, 0, 0, Duration::ZERO);
+        let (ours, theirs) = (stamped(NONCE), stamped(NONCE + 1));
+        let unstamped = ours[STAMP_LENGTH..].to_vec();
+        [
+            reply((IDENTIFIER + 1, 0), theirs.clone(), sent),
+            reply((IDENTIFIER + 1, 0), unstamped, sent),
+            reply((IDENTIFIER, 0), theirs.clone(), sent),
+            error(Reason::TimeExceeded, (IDENTIFIER, 0), theirs, sent),
+            reply(
+                (IDENTIFIER, 0),
+                echoed(&pinger, NONCE, 1, 0, Duration::ZERO),
+                sent,
+            ),
+            Datagram {
+                message: Received::Other,
+                ..reply((IDENTIFIER, 0), ours, sent)
+            },
+        ]
+        .into_iter()
+        .for_each(|datagram| assert!(pinger.attribute(datagram).is_none()));
+        assert_eq!(pinger.in_flight, 1);
+        assert!(pinger.outstanding[&(IDENTIFIER, 0)].pending());
+    }
+
+    #[test]
+    fn unstamped_replies_are_matched_by_key_alone() {
+        let mut pinger = pinger(&["10.9.1.2"]).with_payload(
+            Payload::new(Pattern::Repeat(b"ab".to_vec()), Some(STAMP_LENGTH - 1)).unwrap(),
+        );
+        let sent = pinger.epoch;
+        pinger.track(0, 3, (IDENTIFIER, 0), sent);
+        let payload = echoed(&pinger, NONCE, 0, 3, Duration::ZERO);
+        let event = pinger.attribute(reply((IDENTIFIER, 0), payload.clone(), sent));
+        assert_eq!(replied(event).1, 3);
+        assert!(pinger
+            .attribute(reply((IDENTIFIER, 1), payload, sent))
+            .is_none());
+    }
+
+    #[test]
+    fn copies_of_a_reply_are_duplicates() {
+        let mut pinger = pinger(&["10.9.1.2"]);
+        let sent = pinger.epoch;
+        pinger.track(0, 0, (IDENTIFIER, 0), sent);
+        let payload = echoed(&pinger, NONCE, 0, 0, Duration::ZERO);
+        let first = pinger.attribute(reply(
+            (IDENTIFIER, 0),
+            payload.clone(),
+            sent + Duration::from_micros(100),
+        ));
+        assert!(!replied(first).3);
+        let copy = pinger.attribute(reply(
+            (IDENTIFIER, 0),
+            payload,
+            sent + Duration::from_micros(900),
+        ));
+        match copy {
+            // Copies are timed, but left out of the variation.
+            Some(Event::Reply(reply)) => {
+                assert!(reply.duplicate);
+                assert_eq!(reply.round_trip, Duration::from_micros(900));
+                assert_eq!(reply.variation.mean_difference(), None);
+            }
+            event => panic!("expected a reply, got {:?}", event),
+        }
+        assert_eq!(pinger.in_flight, 0);
+    }
+
+    #[test]
+    fn errors_settle_probes() {
+        let mut pinger = pinger(&["10.9.1.2"]);
+        let sent = pinger.epoch;
+        pinger.track(0, 0, (IDENTIFIER, 0), sent);
+        let payload = echoed(&pinger, NONCE, 0, 0, Duration::ZERO);
+        let event = pinger.attribute(error(
+            Reason::TimeExceeded,
+            (IDENTIFIER, 0),
+            payload.clone(),
+            sent,
+        ));
+        assert_eq!(undelivered(event), (0, Reason::TimeExceeded, false));
+        assert_eq!(pinger.in_flight, 0);
+        let event = pinger.attribute(error(
+            Reason::TimeExceeded,
+            (IDENTIFIER, 0),
+            payload.clone(),
+            sent,
+        ));
+        assert_eq!(undelivered(event), (0, Reason::TimeExceeded, true));
+        assert!(replied(pinger.attribute(reply((IDENTIFIER, 0), payload, sent))).3);
+        assert_eq!(pinger.in_flight, 0);
+    }
+
+    #[test]
+    fn redirects_leave_probes_in_flight() {
+        let mut pinger = pinger(&["10.9.1.2"]);
+        let sent = pinger.epoch;
+        pinger.track(0, 0, (IDENTIFIER, 0), sent);
+        let payload = echoed(&pinger, NONCE, 0, 0, Duration::ZERO);
+        let redirect = Reason::Redirect { gateway: ROUTER };
+        let event = pinger.attribute(error(redirect, (IDENTIFIER, 0), payload.clone(), sent));
+        assert_eq!(undelivered(event), (0, redirect, false));
+        assert_eq!(pinger.in_flight, 1);
+        assert!(!replied(pinger.attribute(reply((IDENTIFIER, 0), payload, sent))).3);
+        assert_eq!(pinger.in_flight, 0);
+    }
+
+    #[test]
+    fn late_replies_are_reported_once_timed_out() {
+        let mut pinger = pinger(&["10.9.1.2"]);
+        let sent = pinger.epoch;
+        pinger.track(0, 0, (IDENTIFIER, 0), sent);
+        assert_eq!(pinger.expire(sent + DEFAULT_TIMEOUT).len(), 1);
+        assert_eq!(pinger.in_flight, 0);
+        let payload = echoed(&pinger, NONCE, 0, 0, Duration::ZERO);
+        let late = sent + DEFAULT_TIMEOUT + Duration::from_millis(1);
+        let event = pinger.attribute(reply((IDENTIFIER, 0), payload, late));
+        assert_eq!(
+            replied(event),
+            (
+                "10.9.1.2".parse().unwrap(),
+                0,
+                DEFAULT_TIMEOUT + Duration::from_millis(1),
+                false
+            )
+        );
+        assert_eq!(pinger.in_flight, 0);
+        assert!(pinger.expire(late + DEFAULT_TIMEOUT).is_empty());
+    }
+
+    #[test]
+    fn forgotten_probes_are_timed_from_their_stamp() {
+        let mut pinger = pinger(&["10.9.1.2", "10.9.0.2"]);
+        let stamped = Duration::from_millis(5);
+        let payload = echoed(&pinger, NONCE, 1, 4, stamped);
+        let arrival = pinger.epoch + stamped + Duration::from_micros(300);
+        let event = pinger.attribute(reply((IDENTIFIER, 9), payload, arrival));
+        assert_eq!(
+            replied(event),
+            (
+                "10.9.0.2".parse().unwrap(),
+                4,
+                Duration::from_micros(300),
+                false
+            )
+        );
+        assert_eq!(pinger.in_flight, 0);
+    }
+
+    #[test]
+    fn stamps_win_over_a_reused_key() {
+        let mut pinger = pinger(&["10.9.1.2"]);
+        let sent = pinger.epoch + Duration::from_secs(2);
+        // The key has since been reused for sequence 5, which is still in
+        // flight; the reply is for sequence 1, stamped two seconds earlier.
+        pinger.track(0, 5, (IDENTIFIER, 0), sent);
+        let payload = echoed(&pinger, NONCE, 0, 1, Duration::ZERO);
+        let arrival = sent + Duration::from_millis(10);
+        let event = pinger.attribute(reply((IDENTIFIER, 0), payload, arrival));
+        assert_eq!(
+            replied(event),
+            (
+                "10.9.1.2".parse().unwrap(),
+                1,
+                Duration::from_millis(2010),
+                false
+            )
+        );
+        assert_eq!(pinger.in_flight, 1);
+        assert!(pinger.outstanding[&(IDENTIFIER, 0)].pending());
+    }
+
+    #[test]
+    fn reused_keys_forget_probes_in_flight() {
+        let mut pinger = pinger(&["10.9.1.2"]);
+        let sent = pinger.epoch;
+        pinger.track(0, 0, (IDENTIFIER, 0), sent);
+        pinger.track(0, 1, (IDENTIFIER, 0), sent);
+        assert_eq!(pinger.in_flight, 1);
+        assert_eq!(pinger.expire(sent + DEFAULT_TIMEOUT).len(), 1);
+        assert_eq!(pinger.in_flight, 0);
+    }
+
+    #[test]
+    fn send_stamps_time_probes_until_answered() {
+        let mut pinger = pinger(&["10.9.1.2"]);
+        let sent = pinger.epoch;
+        pinger.track(0, 0, (IDENTIFIER, 0), sent);
+        let stamp = |clock, after| Datagram {
+            message: Received::Sent {
+                identifier: IDENTIFIER,
+                sequence: 0,
+            },
+            source: None,
+            arrival: sent + Duration::from_micros(after),
+            clock,
+        };
+        assert!(pinger.attribute(stamp(Clock::Kernel, 50)).is_none());
+        // A stamp is only taken over one from a less precise clock.
+        assert!(pinger.attribute(stamp(Clock::Kernel, 80)).is_none());
+        let payload = echoed(&pinger, NONCE, 0, 0, Duration::ZERO);
+        let arrival = sent + Duration::from_micros(250);
+        match pinger.attribute(reply((IDENTIFIER, 0), payload.clone(), arrival)) {
+            Some(Event::Reply(reply)) => {
+                assert_eq!(reply.round_trip, Duration::from_micros(200));
+                assert_eq!(reply.sent_clock, Clock::Kernel);
+            }
+            event => panic!("expected a reply, got {:?}", event),
+        }
+        // Nor once the reply has been timed by it.
+        assert!(pinger.attribute(stamp(Clock::Hardware, 60)).is_none());
+        let copy = pinger.attribute(reply((IDENTIFIER, 0), payload, arrival));
+        assert_eq!(replied(copy).2, Duration::from_micros(200));
+    }
+}
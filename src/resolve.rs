@@ -0,0 +1,193 @@
+use {
+    crate::Error,
+    derive_more::From,
+    std::{
+        collections::HashMap,
+        fmt::{self, Display, Formatter},
+        fs,
+        net::{IpAddr, ToSocketAddrs},
+        path::Path,
+    },
+};
+
+/// A destination as given on the command line: an address, or a name to resolve.
+#[derive(Clone, Debug, From)]
+pub enum Host {
+    Address(IpAddr),
+    Name(String),
+}
+
+impl<'a> From<&'a str> for Host {
+    fn from(text: &'a str) -> Self {
+        text.parse::<IpAddr>()
+            .map(Host::Address)
+            .unwrap_or_else(|_| Host::Name(text.to_string()))
+    }
+}
+
+impl Display for Host {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Address(address) => address.fmt(formatter),
+            Self::Name(name) => name.fmt(formatter),
+        }
+    }
+}
+
+/// Which address family to pick when a name resolves to both.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Family {
+    #[default]
+    Any,
+    V4,
+    V6,
+}
+
+impl Family {
+    fn admits(self, address: &IpAddr) -> bool {
+        match self {
+            Self::Any => true,
+            Self::V4 => address.is_ipv4(),
+            Self::V6 => address.is_ipv6(),
+        }
+    }
+}
+
+/// Turns a [`Host`] into the address to ping.
+///
+/// Names listed in the hosts override are answered from it alone; everything
+/// else goes to the system resolver.
+#[derive(Clone, Debug, Default)]
+pub struct Resolver {
+    family: Family,
+    hosts: HashMap<String, Vec<IpAddr>>,
+}
+
+impl Resolver {
+    pub fn with_family(self, family: Family) -> Self {
+        Self { family, ..self }
+    }
+
+    /// Adds the entries of an `/etc/hosts`-style file to the override: one
+    /// address per line followed by its names, with `#` starting a comment.
+    pub fn with_hosts_file<P: AsRef<Path>>(mut self, path: P) -> Result<Self, Error> {
+        fs::read_to_string(path)?
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or_default())
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                fields.next().map(|address| (address, fields))
+            })
+            .try_for_each(|(address, names)| {
+                let address = address.parse::<IpAddr>()?;
+                names.for_each(|name| {
+                    self.hosts
+                        .entry(name.to_ascii_lowercase())
+                        .or_default()
+                        .push(address)
+                });
+                Ok::<_, Error>(())
+            })?;
+        Ok(self)
+    }
+
+    pub fn resolve(&self, host: &Host) -> Result<IpAddr, Error> {
+        let candidates = match host {
+            Host::Address(address) => vec![*address],
+            Host::Name(name) => match self.hosts.get(&name.to_ascii_lowercase()) {
+                Some(addresses) => addresses.clone(),
+                None => (name.as_str(), 0)
+                    .to_socket_addrs()
+                    .map_err(|error| format!("cannot resolve {}: {}", name, error))?
+                    .map(|address| address.ip())
+                    .collect(),
+            },
+        };
+        candidates
+            .into_iter()
+            .find(|address| self.family.admits(address))
+            .ok_or_else(|| match self.family {
+                Family::Any => format!("{} has no address", host).into(),
+                Family::V4 => format!("{} has no IPv4 address", host).into(),
+                Family::V6 => format!("{} has no IPv6 address", host).into(),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        std::{env, process},
+    };
+
+    const HOSTS: &str = "\
+# routers
+10.9.0.2    router r1   # the first hop
+fd09::2     router
+10.9.1.2\tTarget
+   # 10.9.9.9 commented
+fd19::2 v6-only
+";
+
+    /// A resolver with `hosts` as its override, written out to a file named
+    /// after the test so that tests running side by side keep to their own.
+    fn overriding(test: &str, hosts: &str) -> Result<Resolver, Error> {
+        let path = env::temp_dir().join(format!("icmp-echo-{}-{}", process::id(), test));
+        fs::write(&path, hosts)?;
+        let resolver = Resolver::default().with_hosts_file(&path);
+        fs::remove_file(&path)?;
+        resolver
+    }
+
+    /// The address `host` resolves to, or why it does not.
+    fn resolve(resolver: &Resolver, host: &str) -> String {
+        resolver
+            .resolve(&Host::from(host))
+            .map_or_else(|error| error.to_string(), |address| address.to_string())
+    }
+
+    #[test]
+    fn names_come_from_the_override() {
+        let resolver = overriding("override", HOSTS).unwrap();
+        assert_eq!(resolve(&resolver, "router"), "10.9.0.2");
+        assert_eq!(resolve(&resolver, "r1"), "10.9.0.2");
+        assert_eq!(resolve(&resolver, "v6-only"), "fd19::2");
+        assert_eq!(resolve(&resolver, "10.9.9.9"), "10.9.9.9");
+    }
+
+    #[test]
+    fn names_match_whatever_their_case() {
+        let resolver = overriding("case", HOSTS).unwrap();
+        assert_eq!(resolve(&resolver, "target"), "10.9.1.2");
+        assert_eq!(resolve(&resolver, "TARGET"), "10.9.1.2");
+        assert_eq!(resolve(&resolver, "Router"), "10.9.0.2");
+    }
+
+    #[test]
+    fn families_filter_addresses() {
+        let resolver = overriding("families", HOSTS).unwrap();
+        let v4 = resolver.clone().with_family(Family::V4);
+        let v6 = resolver.with_family(Family::V6);
+        assert_eq!(resolve(&v4, "router"), "10.9.0.2");
+        assert_eq!(resolve(&v6, "router"), "fd09::2");
+        assert_eq!(resolve(&v4, "v6-only"), "v6-only has no IPv4 address");
+        assert_eq!(resolve(&v6, "10.9.1.2"), "10.9.1.2 has no IPv6 address");
+    }
+
+    #[test]
+    fn overridden_names_never_reach_the_system() {
+        let resolver = overriding("system", "10.9.0.2 localhost\n").unwrap();
+        assert_eq!(resolve(&resolver, "LocalHost"), "10.9.0.2");
+        assert_eq!(
+            resolve(&resolver.with_family(Family::V6), "localhost"),
+            "localhost has no IPv6 address"
+        );
+    }
+
+    #[test]
+    fn bad_addresses_are_refused() {
+        assert!(overriding("bad", "10.9.0.300 router\n").is_err());
+        assert!(overriding("nameless", "router\n").is_err());
+    }
+}
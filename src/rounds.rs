@@ -0,0 +1,57 @@
+use {
+    crate::{socket::Marking, Error, Event, Host, Payload, Pinger, RequestsToSend},
+    futures_util::stream::Stream,
+    std::net::IpAddr,
+};
+
+/// A single destination probed round after round, the way path MTU
+/// discovery tries one payload size after another and a trace one time to
+/// live after another.
+///
+/// Every round sends the destination's request count at its interval, over
+/// the sockets and under the identifier of one [`Pinger`], with only the
+/// payload or marking changed in between. Anything else the pinger was set up
+/// with, such as its timeout or source, holds for every round.
+pub(crate) struct Rounds {
+    pinger: Pinger,
+    pub(crate) host: Host,
+    pub(crate) destination: IpAddr,
+    /// The number of requests in a round.
+    pub(crate) requests: u64,
+}
+
+impl Rounds {
+    /// Takes over `pinger`, which must have a single destination with a
+    /// number of requests; `round` names what a round probes, for the errors
+    /// otherwise.
+    pub(crate) fn new(pinger: Pinger, round: &str) -> Result<Self, Error> {
+        let targets = pinger
+            .targets()
+            .zip(pinger.requests())
+            .map(|((host, destination), requests)| (host.clone(), destination, requests))
+            .collect::<Vec<_>>();
+        match targets.as_slice() {
+            [(host, destination, RequestsToSend::Count(requests))] => Ok(Self {
+                pinger,
+                host: host.clone(),
+                destination: *destination,
+                requests: *requests,
+            }),
+            [(.., RequestsToSend::Continuous)] => Err(format!(
+                "probing {0} by {0} needs a number of requests per {0}",
+                round
+            )
+            .into()),
+            _ => Err(format!("only one destination can be probed {0} by {0}", round).into()),
+        }
+    }
+
+    /// Sends a round of requests, after `configure` has set their payload or
+    /// marking for it.
+    pub(crate) fn round(
+        &mut self,
+        configure: impl FnOnce(&mut Payload, &mut Marking),
+    ) -> impl Stream<Item = Result<Event, Error>> + '_ {
+        self.pinger.again(configure)
+    }
+}
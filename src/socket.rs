@@ -0,0 +1,1029 @@
+use {
+    crate::{Clock, Error, Reason, Unreachable},
+    icmp_socket::{
+        packet::{IcmpPacketBuildError, WithEchoRequest},
+        Icmpv4Packet, Icmpv6Packet,
+    },
+    socket2::{Domain, MaybeUninitSlice, MsgHdrMut, Protocol, SockAddr, Type},
+    std::{
+        collections::HashMap,
+        fs, io,
+        mem::{self, MaybeUninit},
+        net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+        os::fd::AsRawFd,
+        ptr,
+        time::{Duration, Instant, SystemTime},
+    },
+    tokio::io::{unix::AsyncFd, Interest},
+};
+
+/// Large enough for any ICMP message, including a reply quoting a maximum
+/// sized request.
+const BUFFER_SIZE: usize = 65536;
+
+/// Room for the ancillary data the kernel attaches to a datagram.
+const CONTROL_SIZE: usize = 256;
+
+/// How far the network card's timestamp of a datagram may be from the
+/// kernel's, or the stamp of a request leaving from the moment it was handed
+/// to the kernel, before it is taken to be on some other clock. Hardware
+/// clocks that are not synchronised with the system clock fail this test.
+const PLAUSIBLE_SKEW: Duration = Duration::from_secs(1);
+
+/// What `ee_info` holds for the stamp of a request leaving; missing from
+/// `libc`.
+const SCM_TSTAMP_SND: u32 = 0;
+
+/// How many requests awaiting their send stamp are remembered.
+const UNSTAMPED: u32 = 1 << 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Version {
+    V4,
+    V6,
+}
+
+/// How a socket reaches the network.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    /// A raw socket, which needs root or `CAP_NET_RAW`.
+    Raw,
+    /// A Linux ping socket (`SOCK_DGRAM` with `IPPROTO_ICMP` or
+    /// `IPPROTO_ICMPV6`), open to the groups in
+    /// `net.ipv4.ping_group_range`. The kernel replaces the identifier of
+    /// every request with the socket's own and only hands back replies to
+    /// it, without their IPv4 header. ICMP errors about its requests are
+    /// queued on the socket's error queue.
+    Ping,
+}
+
+/// A non-blocking ICMP or ICMPv6 socket registered with the Tokio reactor,
+/// shared by every destination of its family. A raw socket is used where
+/// allowed, and a ping socket otherwise.
+///
+/// This deliberately does not use `icmp_socket`'s sockets, which shrink the
+/// kernel receive buffer to 512 bytes and so drop replies as soon as more
+/// than one probe is in flight; only its packet types are used.
+pub(crate) struct Socket {
+    version: Version,
+    kind: Kind,
+    inner: AsyncFd<socket2::Socket>,
+    buffer: Vec<MaybeUninit<u8>>,
+    control: Vec<MaybeUninit<u8>>,
+    /// Whether the kernel reports when requests leave on the error queue.
+    stamps_sends: bool,
+    /// How many requests have been handed to the kernel, which numbers the
+    /// send stamps it reports from zero.
+    sends: u32,
+    /// The identifier, sequence number and user space send time of recent
+    /// requests, by the number of their send stamp.
+    unstamped: HashMap<u32, (u16, u16, Instant)>,
+}
+
+/// How the IP header of outgoing requests is set, where it differs from the
+/// system's defaults.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Marking {
+    /// IPv4 time to live or IPv6 hop limit.
+    pub(crate) ttl: Option<u8>,
+    /// IPv4 type of service or IPv6 traffic class.
+    pub(crate) tos: Option<u8>,
+    /// Whether to set the IPv4 Don't Fragment bit, and to keep the kernel
+    /// from fragmenting IPv6 requests, however large.
+    pub(crate) dont_fragment: bool,
+}
+
+/// An incoming ICMP message, where it came from and when it arrived.
+pub(crate) struct Datagram {
+    pub(crate) message: Received,
+    pub(crate) source: Option<IpAddr>,
+    /// When the message arrived, or for [`Received::Sent`] when the request
+    /// left, as told by `clock` and carried over to the monotonic clock.
+    pub(crate) arrival: Instant,
+    pub(crate) clock: Clock,
+}
+
+/// The part of an incoming ICMP message the pinger cares about.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Received {
+    EchoReply {
+        identifier: u16,
+        sequence: u16,
+        /// The reply's IPv4 time to live or IPv6 hop limit.
+        ttl: Option<u8>,
+        /// The reply's IPv4 type of service or IPv6 traffic class.
+        tos: Option<u8>,
+        payload: Vec<u8>,
+    },
+    /// An ICMP error quoting an echo request.
+    Error {
+        reason: Reason,
+        identifier: u16,
+        sequence: u16,
+        /// As much of the request's payload as the error quoted.
+        payload: Vec<u8>,
+    },
+    /// The kernel or the network card stamped an echo request as it left.
+    Sent {
+        identifier: u16,
+        sequence: u16,
+    },
+    Other,
+}
+
+impl TryFrom<IpAddr> for Socket {
+    type Error = Error;
+    /// Opens a socket of `destination`'s family. Must be called from within
+    /// a Tokio runtime.
+    fn try_from(destination: IpAddr) -> Result<Self, Self::Error> {
+        let (version, domain, protocol) = match destination {
+            IpAddr::V4(_) => (Version::V4, Domain::IPV4, Protocol::ICMPV4),
+            IpAddr::V6(_) => (Version::V6, Domain::IPV6, Protocol::ICMPV6),
+        };
+        let (kind, inner) = socket2::Socket::new(domain, Type::RAW, Some(protocol))
+            .map(|inner| (Kind::Raw, inner))
+            .or_else(|raw| match raw.kind() {
+                io::ErrorKind::PermissionDenied => {
+                    socket2::Socket::new(domain, Type::DGRAM, Some(protocol))
+                        .map(|inner| (Kind::Ping, inner))
+                        .map_err(|ping| denied(raw, ping))
+                }
+                _ => Err(raw.into()),
+            })?;
+        inner.set_nonblocking(true)?;
+        // Send stamps are numbered, and come without a copy of the request.
+        // Without kernel timestamps, sends and arrivals are timed in user
+        // space.
+        let stamps_sends = set_option(
+            &inner,
+            libc::SOL_SOCKET,
+            libc::SO_TIMESTAMPING,
+            (libc::SOF_TIMESTAMPING_RX_HARDWARE
+                | libc::SOF_TIMESTAMPING_TX_HARDWARE
+                | libc::SOF_TIMESTAMPING_RAW_HARDWARE
+                | libc::SOF_TIMESTAMPING_RX_SOFTWARE
+                | libc::SOF_TIMESTAMPING_TX_SOFTWARE
+                | libc::SOF_TIMESTAMPING_SOFTWARE
+                | libc::SOF_TIMESTAMPING_OPT_ID
+                | libc::SOF_TIMESTAMPING_OPT_TSONLY) as libc::c_int,
+        )
+        .is_ok();
+        if !stamps_sends {
+            let _ = set_option(&inner, libc::SOL_SOCKET, libc::SO_TIMESTAMPNS, 1);
+        }
+        // Ping sockets leave out the IPv4 header, and with it the TTL and
+        // TOS, and only report ICMP errors through the error queue. ICMPv6
+        // sockets never deliver the header.
+        match (version, kind) {
+            (Version::V4, Kind::Ping) => {
+                set_option(&inner, libc::IPPROTO_IP, libc::IP_RECVTTL, 1)?;
+                inner.set_recv_tos(true)?;
+                set_option(&inner, libc::IPPROTO_IP, libc::IP_RECVERR, 1)?;
+            }
+            (Version::V6, Kind::Ping) => {
+                set_option(&inner, libc::IPPROTO_IPV6, libc::IPV6_RECVHOPLIMIT, 1)?;
+                inner.set_recv_tclass_v6(true)?;
+                set_option(&inner, libc::IPPROTO_IPV6, libc::IPV6_RECVERR, 1)?;
+            }
+            (Version::V6, Kind::Raw) => {
+                set_option(&inner, libc::IPPROTO_IPV6, libc::IPV6_RECVHOPLIMIT, 1)?;
+                inner.set_recv_tclass_v6(true)?;
+            }
+            (Version::V4, Kind::Raw) => {}
+        }
+        Ok(Self {
+            version,
+            kind,
+            inner: AsyncFd::new(inner)?,
+            buffer: vec![MaybeUninit::new(0); BUFFER_SIZE],
+            control: vec![MaybeUninit::new(0); CONTROL_SIZE],
+            stamps_sends,
+            sends: 0,
+            unstamped: HashMap::new(),
+        })
+    }
+}
+
+/// Explains why neither a raw nor a ping socket could be opened.
+fn denied(raw: io::Error, ping: io::Error) -> Error {
+    let range = fs::read_to_string("/proc/sys/net/ipv4/ping_group_range")
+        .map(|range| range.split_whitespace().collect::<Vec<_>>().join(" "))
+        .unwrap_or_else(|_| "unknown".to_string());
+    format!(
+        "cannot open an ICMP socket: raw sockets need root or CAP_NET_RAW ({}), \
+         and ping sockets need one of the user's groups in \
+         net.ipv4.ping_group_range, currently {} ({})",
+        raw, range, ping
+    )
+    .into()
+}
+
+impl Socket {
+    /// Whether the kernel replaces the identifier of the requests sent on
+    /// this socket, and so of the replies it receives.
+    pub(crate) fn rewrites_identifier(&self) -> bool {
+        self.kind == Kind::Ping
+    }
+
+    /// Whether `destination` is of this socket's address family.
+    pub(crate) fn serves(&self, destination: &IpAddr) -> bool {
+        matches!(
+            (self.version, destination),
+            (Version::V4, IpAddr::V4(_)) | (Version::V6, IpAddr::V6(_))
+        )
+    }
+
+    /// Sends from `source` and only receives what is addressed to it.
+    pub(crate) fn bind(&self, source: IpAddr) -> io::Result<()> {
+        self.inner
+            .get_ref()
+            .bind(&SocketAddr::new(source, 0).into())
+    }
+
+    /// Sends through `interface` and only receives what arrives on it,
+    /// whatever the routing table says.
+    pub(crate) fn bind_device(&self, interface: &str) -> io::Result<()> {
+        self.inner.get_ref().bind_device(Some(interface.as_bytes()))
+    }
+
+    /// The address the socket is bound to, if any.
+    pub(crate) fn source(&self) -> Option<IpAddr> {
+        self.inner
+            .get_ref()
+            .local_addr()
+            .ok()
+            .and_then(|address| address.as_socket())
+            .map(|address| address.ip())
+            .filter(|source| !source.is_unspecified())
+    }
+
+    /// The interface the socket is bound to, if any.
+    pub(crate) fn device(&self) -> Option<String> {
+        self.inner
+            .get_ref()
+            .device()
+            .ok()
+            .flatten()
+            .map(|device| String::from_utf8_lossy(&device).into_owned())
+    }
+
+    /// Sends an ICMP or ICMPv6 echo request, whichever `destination` calls for.
+    ///
+    /// ICMPv6 requests go out without a checksum: the kernel fills it in for
+    /// ICMPv6 sockets, as it alone knows the source address. Yields the time
+    /// the request was handed to the kernel, read right before the send
+    /// call; where the kernel stamps requests as they leave, that stamp is
+    /// received later as [`Received::Sent`].
+    pub(crate) async fn send_echo(
+        &mut self,
+        destination: IpAddr,
+        identifier: u16,
+        sequence: u16,
+        payload: Vec<u8>,
+    ) -> Result<io::Result<Instant>, IcmpPacketBuildError> {
+        let bytes = match self.version {
+            Version::V4 => Icmpv4Packet::with_echo_request(identifier, sequence, payload)
+                .map(|packet| packet.with_checksum().get_bytes(true))?,
+            Version::V6 => Icmpv6Packet::with_echo_request(identifier, sequence, payload)
+                .map(|packet| packet.get_bytes(true))?,
+        };
+        let destination = SockAddr::from(SocketAddr::new(destination, 0));
+        // An ICMP error for an earlier request leaves a ping socket's error
+        // pending as well as queued, and the kernel would fail this send with
+        // it. The queued copy is what reports it.
+        if let (Kind::Ping, Err(error)) = (self.kind, self.inner.get_ref().take_error()) {
+            return Ok(Err(error));
+        }
+        let sent = self
+            .inner
+            .async_io(Interest::WRITABLE, |inner| {
+                let send_time = Instant::now();
+                inner.send_to(&bytes, &destination).map(|_| send_time)
+            })
+            .await;
+        if let (true, Ok(send_time)) = (self.stamps_sends, &sent) {
+            self.unstamped.remove(&self.sends.wrapping_sub(UNSTAMPED));
+            self.unstamped
+                .insert(self.sends, (identifier, sequence, *send_time));
+            self.sends = self.sends.wrapping_add(1);
+        }
+        Ok(sent)
+    }
+
+    /// Applies `marking` to every request sent from now on.
+    pub(crate) fn mark(&self, marking: Marking) -> io::Result<()> {
+        let socket = self.inner.get_ref();
+        marking.ttl.map_or(Ok(()), |ttl| match self.version {
+            Version::V4 => socket.set_ttl(ttl.into()),
+            Version::V6 => socket.set_unicast_hops_v6(ttl.into()),
+        })?;
+        marking.tos.map_or(Ok(()), |tos| match self.version {
+            Version::V4 => socket.set_tos(tos.into()),
+            Version::V6 => socket.set_tclass_v6(tos.into()),
+        })?;
+        // Probing rather than regular discovery sends requests larger than
+        // the path MTU the kernel has learnt, instead of failing them.
+        match (marking.dont_fragment, self.version) {
+            (false, _) => Ok(()),
+            (true, Version::V4) => set_option(
+                socket,
+                libc::IPPROTO_IP,
+                libc::IP_MTU_DISCOVER,
+                libc::IP_PMTUDISC_PROBE,
+            ),
+            (true, Version::V6) => set_option(
+                socket,
+                libc::IPPROTO_IPV6,
+                libc::IPV6_MTU_DISCOVER,
+                libc::IPV6_PMTUDISC_PROBE,
+            ),
+        }
+    }
+
+    /// Receives the next ICMP message, timed by the kernel or the network
+    /// card where they stamp it and by the time it is read otherwise, or the
+    /// stamp of a request leaving. The error queue, where ping sockets get
+    /// ICMP errors and every socket its send stamps, is drained first.
+    pub(crate) async fn rcv_from(&mut self) -> io::Result<Datagram> {
+        let (buffer, control) = (&mut self.buffer, &mut self.control);
+        let mut address = SockAddr::from(SocketAddr::new(
+            match self.version {
+                Version::V4 => Ipv4Addr::UNSPECIFIED.into(),
+                Version::V6 => Ipv6Addr::UNSPECIFIED.into(),
+            },
+            0,
+        ));
+        let (interest, queues): (_, &[_]) = match (self.kind, self.stamps_sends) {
+            (Kind::Raw, false) => (Interest::READABLE, &[0]),
+            _ => (
+                Interest::READABLE.add(Interest::ERROR),
+                &[libc::MSG_ERRQUEUE, 0],
+            ),
+        };
+        let (length, control_length, queued) = self
+            .inner
+            .async_io(interest, |inner| {
+                let mut receive = |flags| {
+                    let mut buffers = [MaybeUninitSlice::new(&mut buffer[..])];
+                    let mut message = MsgHdrMut::new()
+                        .with_addr(&mut address)
+                        .with_buffers(&mut buffers)
+                        .with_control(&mut control[..]);
+                    inner
+                        .recvmsg(&mut message, flags)
+                        .map(|length| (length, message.control_len(), flags != 0))
+                };
+                queues
+                    .iter()
+                    .map(|flags| receive(*flags))
+                    .find(|received| {
+                        !matches!(received, Err(error) if error.kind() == io::ErrorKind::WouldBlock)
+                    })
+                    .unwrap_or_else(|| Err(io::ErrorKind::WouldBlock.into()))
+            })
+            .await?;
+        let (now, wall) = (Instant::now(), SystemTime::now());
+        // SAFETY: `recvmsg` initialised the first `length` bytes of the
+        // buffer and `control_length` bytes of the control buffer, and both
+        // were zeroed on creation anyway.
+        let (bytes, control) = unsafe {
+            (
+                &*(&self.buffer[..length] as *const [MaybeUninit<u8>] as *const [u8]),
+                &*(&self.control[..control_length] as *const [MaybeUninit<u8>] as *const [u8]),
+            )
+        };
+        let ancillary = ancillary(control);
+        // However far reading has fallen behind, the kernel's stamps are on
+        // the system clock.
+        let stamps = ancillary
+            .timestamps
+            .iter()
+            .filter_map(|&(clock, at)| {
+                now.checked_sub(wall.duration_since(at).unwrap_or_default())
+                    .map(|at| (clock, at))
+            })
+            .collect::<Vec<_>>();
+        let arrival = stamps
+            .iter()
+            .find(|(clock, _)| *clock == Clock::Kernel)
+            .and_then(|&(_, kernel)| plausible(&stamps, kernel))
+            .unwrap_or((now, Clock::User));
+        let ((arrival, clock), message, source) = match (queued, ancillary.error, ancillary.sent) {
+            (true, Some(error), _) => (
+                arrival,
+                reason(self.version, error.kind, error.code, error.info)
+                    .zip(echo(self.version, Direction::Request, bytes))
+                    .map_or(
+                        Received::Other,
+                        |(reason, (identifier, sequence, payload))| Received::Error {
+                            reason,
+                            identifier,
+                            sequence,
+                            payload: payload.to_vec(),
+                        },
+                    ),
+                error.offender,
+            ),
+            (true, None, Some(key)) => self
+                .unstamped
+                .get(&key)
+                .and_then(|&(identifier, sequence, send_time)| {
+                    plausible(&stamps, send_time).map(|left| {
+                        (
+                            left,
+                            Received::Sent {
+                                identifier,
+                                sequence,
+                            },
+                            None,
+                        )
+                    })
+                })
+                .unwrap_or((arrival, Received::Other, None)),
+            (true, None, None) => (arrival, Received::Other, None),
+            (false, ..) => (
+                arrival,
+                decode(self.version, self.kind, bytes, &ancillary),
+                address.as_socket().map(|address| address.ip()),
+            ),
+        };
+        Ok(Datagram {
+            message,
+            source,
+            arrival,
+            clock,
+        })
+    }
+}
+
+fn set_option(
+    socket: &socket2::Socket,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: libc::c_int,
+) -> io::Result<()> {
+    // SAFETY: `value` outlives the call and its size is passed along.
+    match unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// What the kernel attached to a datagram.
+#[derive(Default)]
+struct Ancillary {
+    /// When the datagram arrived or, for a send stamp, the request left, most
+    /// precise first: the network card's, then the kernel's.
+    timestamps: Vec<(Clock, SystemTime)>,
+    /// The IPv4 time to live or IPv6 hop limit, for sockets that do not
+    /// deliver the header.
+    ttl: Option<u8>,
+    /// The type of service or traffic class, likewise.
+    tos: Option<u8>,
+    /// Where a message read from the error queue came from.
+    error: Option<QueuedError>,
+    /// The number of the request a message read from the error queue is the
+    /// send stamp of.
+    sent: Option<u32>,
+}
+
+/// The ICMP error behind a message on a ping socket's error queue.
+#[derive(Clone, Copy)]
+struct QueuedError {
+    kind: u8,
+    code: u8,
+    info: u32,
+    offender: Option<IpAddr>,
+}
+
+fn ancillary(control: &[u8]) -> Ancillary {
+    let read = |data: *const u8, index: usize| {
+        // SAFETY: callers only read timespecs the control message holds.
+        let time: libc::timespec =
+            unsafe { ptr::read_unaligned((data as *const libc::timespec).add(index)) };
+        match (time.tv_sec, time.tv_nsec) {
+            (0, 0) => None,
+            (seconds, nanoseconds) => {
+                Some(SystemTime::UNIX_EPOCH + Duration::new(seconds as u64, nanoseconds as u32))
+            }
+        }
+    };
+    let mut found = Ancillary::default();
+    // SAFETY: the header only points the macros at `control`, which the
+    // kernel filled with well formed control messages.
+    unsafe {
+        let mut header: libc::msghdr = mem::zeroed();
+        header.msg_control = control.as_ptr() as *mut libc::c_void;
+        header.msg_controllen = control.len() as _;
+        let mut message = libc::CMSG_FIRSTHDR(&header);
+        while !message.is_null() {
+            let data = libc::CMSG_DATA(message) as *const u8;
+            match ((*message).cmsg_level, (*message).cmsg_type) {
+                // Software, a legacy field, then the raw hardware stamp.
+                (libc::SOL_SOCKET, libc::SCM_TIMESTAMPING) => {
+                    found
+                        .timestamps
+                        .extend(read(data, 2).map(|at| (Clock::Hardware, at)));
+                    found
+                        .timestamps
+                        .extend(read(data, 0).map(|at| (Clock::Kernel, at)));
+                }
+                (libc::SOL_SOCKET, libc::SCM_TIMESTAMPNS) => found
+                    .timestamps
+                    .extend(read(data, 0).map(|at| (Clock::Kernel, at))),
+                (libc::IPPROTO_IP, libc::IP_RECVERR) | (libc::IPPROTO_IPV6, libc::IPV6_RECVERR) => {
+                    let error = ptr::read_unaligned(data as *const libc::sock_extended_err);
+                    // The offending address follows the error itself.
+                    let offender = data.add(mem::size_of::<libc::sock_extended_err>());
+                    match (error.ee_origin, error.ee_info) {
+                        (libc::SO_EE_ORIGIN_ICMP | libc::SO_EE_ORIGIN_ICMP6, _) => {
+                            found.error = Some(QueuedError {
+                                kind: error.ee_type,
+                                code: error.ee_code,
+                                info: error.ee_info,
+                                offender: address(offender),
+                            })
+                        }
+                        (libc::SO_EE_ORIGIN_TIMESTAMPING, SCM_TSTAMP_SND) => {
+                            found.sent = Some(error.ee_data)
+                        }
+                        _ => {}
+                    }
+                }
+                (libc::IPPROTO_IP, libc::IP_TOS) => found.tos = Some(*data),
+                (libc::IPPROTO_IPV6, libc::IPV6_TCLASS) => {
+                    found.tos = u8::try_from(ptr::read_unaligned(data as *const libc::c_int)).ok()
+                }
+                (libc::IPPROTO_IP, libc::IP_TTL) | (libc::IPPROTO_IPV6, libc::IPV6_HOPLIMIT) => {
+                    found.ttl = u8::try_from(ptr::read_unaligned(data as *const libc::c_int)).ok()
+                }
+                _ => {}
+            }
+            message = libc::CMSG_NXTHDR(&header, message);
+        }
+    }
+    found
+}
+
+/// The most precise of `stamps` that lies within [`PLAUSIBLE_SKEW`] of
+/// `reference`, with the clock it came from.
+fn plausible(stamps: &[(Clock, Instant)], reference: Instant) -> Option<(Instant, Clock)> {
+    stamps
+        .iter()
+        .find(|(_, at)| {
+            let skew = at
+                .saturating_duration_since(reference)
+                .max(reference.saturating_duration_since(*at));
+            skew < PLAUSIBLE_SKEW
+        })
+        .map(|&(clock, at)| (at, clock))
+}
+
+/// Reads a `sockaddr_in` or `sockaddr_in6` from ancillary data.
+///
+/// # Safety
+///
+/// `data` must point to a socket address of the family it starts with.
+unsafe fn address(data: *const u8) -> Option<IpAddr> {
+    match ptr::read_unaligned(data as *const libc::sa_family_t) as libc::c_int {
+        libc::AF_INET => {
+            let address = ptr::read_unaligned(data as *const libc::sockaddr_in);
+            Some(Ipv4Addr::from(u32::from_be(address.sin_addr.s_addr)).into())
+        }
+        libc::AF_INET6 => {
+            let address = ptr::read_unaligned(data as *const libc::sockaddr_in6);
+            Some(Ipv6Addr::from(address.sin6_addr.s6_addr).into())
+        }
+        _ => None,
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Direction {
+    Request,
+    Reply,
+}
+
+/// Splits an echo request or reply into its identifier, sequence number and
+/// payload.
+fn echo(version: Version, direction: Direction, message: &[u8]) -> Option<(u16, u16, &[u8])> {
+    let expected = match (version, direction) {
+        (Version::V4, Direction::Request) => 8,
+        (Version::V4, Direction::Reply) => 0,
+        (Version::V6, Direction::Request) => 128,
+        (Version::V6, Direction::Reply) => 129,
+    };
+    match message {
+        [kind, _code, _, _, identifier_high, identifier_low, sequence_high, sequence_low, payload @ ..]
+            if *kind == expected =>
+        {
+            Some((
+                u16::from_be_bytes([*identifier_high, *identifier_low]),
+                u16::from_be_bytes([*sequence_high, *sequence_low]),
+                payload,
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// What an ICMP or ICMPv6 error message of this type and code reports, if
+/// it is one the pinger understands. `info` is what the kernel reports for
+/// errors on the error queue: the second word of the message, which some
+/// types use, with the pointer of an IPv4 parameter problem shifted down
+/// from its top byte.
+fn reason(version: Version, kind: u8, code: u8, info: u32) -> Option<Reason> {
+    match (version, kind, code) {
+        (Version::V4, 11, _) | (Version::V6, 3, _) => Some(Reason::TimeExceeded),
+        (Version::V4, 3, 4) => Some(Reason::FragmentationNeeded {
+            mtu: Some(info & 0xffff).filter(|mtu| *mtu != 0),
+        }),
+        (Version::V6, 2, _) => Some(Reason::FragmentationNeeded { mtu: Some(info) }),
+        (Version::V4, 3, code) | (Version::V6, 1, code) => {
+            Some(Reason::Unreachable(unreachable(version, code)))
+        }
+        (Version::V4, 4, _) => Some(Reason::SourceQuench),
+        (Version::V4, 5, _) => Some(Reason::Redirect {
+            gateway: Ipv4Addr::from(info).into(),
+        }),
+        (Version::V4, 12, 0) => Some(Reason::ParameterProblem {
+            pointer: Some(info),
+        }),
+        (Version::V4, 12, _) => Some(Reason::ParameterProblem { pointer: None }),
+        (Version::V6, 4, _) => Some(Reason::ParameterProblem {
+            pointer: Some(info),
+        }),
+        _ => None,
+    }
+}
+
+/// What a Destination Unreachable code says could not be reached.
+fn unreachable(version: Version, code: u8) -> Unreachable {
+    match (version, code) {
+        (Version::V4, 0 | 6 | 11) | (Version::V6, 0) => Unreachable::Network,
+        (Version::V4, 1 | 7 | 8 | 12) | (Version::V6, 3) => Unreachable::Host,
+        (Version::V4, 2) => Unreachable::Protocol,
+        (Version::V4, 3) | (Version::V6, 4) => Unreachable::Port,
+        (Version::V4, 9 | 10 | 13) | (Version::V6, 1 | 5 | 6) => Unreachable::Prohibited,
+        (Version::V4, 5) | (Version::V6, 7) => Unreachable::SourceRouteFailed,
+        (_, code) => Unreachable::Other { code },
+    }
+}
+
+/// The echo request quoted in the body of an ICMP error: an IP header, then
+/// the start of the request itself.
+fn quoted(version: Version, body: &[u8]) -> Option<(u16, u16, &[u8])> {
+    let request = match version {
+        Version::V4 => body
+            .first()
+            .filter(|_| body.get(9) == Some(&1))
+            .and_then(|byte| body.get(usize::from(byte & 0x0f) * 4..)),
+        Version::V6 => body.get(40..).filter(|_| body.get(6) == Some(&58)),
+    }?;
+    echo(version, Direction::Request, request)
+}
+
+/// Parses a datagram read from an ICMP socket. Those read from raw IPv4
+/// sockets start with the IP header, which is skipped, options and all,
+/// after reading the TTL and TOS from it.
+fn decode(version: Version, kind: Kind, bytes: &[u8], ancillary: &Ancillary) -> Received {
+    let (message, ttl, tos) = match (version, kind) {
+        (Version::V4, Kind::Raw) => (
+            bytes
+                .first()
+                .and_then(|byte| bytes.get(usize::from(byte & 0x0f) * 4..))
+                .unwrap_or_default(),
+            bytes.get(8).copied(),
+            bytes.get(1).copied(),
+        ),
+        _ => (bytes, ancillary.ttl, ancillary.tos),
+    };
+    if let Some((identifier, sequence, payload)) = echo(version, Direction::Reply, message) {
+        return Received::EchoReply {
+            identifier,
+            sequence,
+            ttl,
+            tos,
+            payload: payload.to_vec(),
+        };
+    }
+    match message {
+        [kind, code, _, _, first, second, third, fourth, body @ ..] => {
+            let word = u32::from_be_bytes([*first, *second, *third, *fourth]);
+            let info = match (version, kind) {
+                (Version::V4, 12) => word >> 24,
+                _ => word,
+            };
+            reason(version, *kind, *code, info)
+                .zip(quoted(version, body))
+                .map_or(
+                    Received::Other,
+                    |(reason, (identifier, sequence, payload))| Received::Error {
+                        reason,
+                        identifier,
+                        sequence,
+                        payload: payload.to_vec(),
+                    },
+                )
+        }
+        _ => Received::Other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDENTIFIER: u16 = 0x1234;
+    const SEQUENCE: u16 = 7;
+
+    /// An ICMP message: type, code, a zero checksum, the second word and
+    /// the body.
+    fn icmp(kind: u8, code: u8, word: [u8; 4], body: &[u8]) -> Vec<u8> {
+        [&[kind, code, 0, 0], &word[..], body].concat()
+    }
+
+    /// An echo request or reply carrying `payload`.
+    fn echoed(kind: u8, payload: &[u8]) -> Vec<u8> {
+        let [identifier_high, identifier_low] = IDENTIFIER.to_be_bytes();
+        let [sequence_high, sequence_low] = SEQUENCE.to_be_bytes();
+        let word = [identifier_high, identifier_low, sequence_high, sequence_low];
+        icmp(kind, 0, word, payload)
+    }
+
+    /// An IPv4 datagram from 10.9.0.1 to 10.9.1.2, with `options` bytes of
+    /// options.
+    fn ipv4(protocol: u8, ttl: u8, tos: u8, options: usize, payload: &[u8]) -> Vec<u8> {
+        let length = (20 + options + payload.len()) as u16;
+        let version = 0x45 + (options / 4) as u8;
+        let header = [0, 0, 0x40, 0, ttl, protocol, 0, 0, 10, 9, 0, 1, 10, 9, 1, 2];
+        let options = vec![1; options];
+        [
+            &[version, tos][..],
+            &length.to_be_bytes(),
+            &header,
+            &options,
+            payload,
+        ]
+        .concat()
+    }
+
+    /// An IPv6 datagram.
+    fn ipv6(next_header: u8, payload: &[u8]) -> Vec<u8> {
+        let [length_high, length_low] = (payload.len() as u16).to_be_bytes();
+        let header = [0x60, 0, 0, 0, length_high, length_low, next_header, 64];
+        [&header[..], &[0xfd; 32], payload].concat()
+    }
+
+    fn error(reason: Reason, payload: &[u8]) -> Received {
+        Received::Error {
+            reason,
+            identifier: IDENTIFIER,
+            sequence: SEQUENCE,
+            payload: payload.to_vec(),
+        }
+    }
+
+    fn decode_v4(bytes: &[u8]) -> Received {
+        decode(Version::V4, Kind::Raw, bytes, &Ancillary::default())
+    }
+
+    fn decode_v6(bytes: &[u8]) -> Received {
+        decode(Version::V6, Kind::Raw, bytes, &Ancillary::default())
+    }
+
+    #[test]
+    fn echo_replies_are_split() {
+        let reply = echoed(0, b"payload");
+        assert_eq!(
+            decode_v4(&ipv4(1, 57, 0xb8, 8, &reply)),
+            Received::EchoReply {
+                identifier: IDENTIFIER,
+                sequence: SEQUENCE,
+                ttl: Some(57),
+                tos: Some(0xb8),
+                payload: b"payload".to_vec(),
+            }
+        );
+        let ancillary = Ancillary {
+            ttl: Some(63),
+            tos: Some(0x88),
+            ..Ancillary::default()
+        };
+        assert_eq!(
+            decode(Version::V4, Kind::Ping, &reply, &ancillary),
+            Received::EchoReply {
+                identifier: IDENTIFIER,
+                sequence: SEQUENCE,
+                ttl: Some(63),
+                tos: Some(0x88),
+                payload: b"payload".to_vec(),
+            }
+        );
+        assert_eq!(
+            decode(Version::V6, Kind::Raw, &echoed(129, b""), &ancillary),
+            Received::EchoReply {
+                identifier: IDENTIFIER,
+                sequence: SEQUENCE,
+                ttl: Some(63),
+                tos: Some(0x88),
+                payload: Vec::new(),
+            }
+        );
+    }
+
+    #[test]
+    fn requests_and_short_messages_are_not_replies() {
+        assert_eq!(
+            decode_v4(&ipv4(1, 64, 0, 0, &echoed(8, b""))),
+            Received::Other
+        );
+        assert_eq!(decode_v6(&echoed(128, b"")), Received::Other);
+        assert_eq!(decode_v6(&echoed(129, b"")[..7]), Received::Other);
+        assert_eq!(decode_v4(&[0x45]), Received::Other);
+        assert_eq!(decode_v4(&[]), Received::Other);
+    }
+
+    #[test]
+    fn time_exceeded_quotes_the_request() {
+        let quoted = ipv4(1, 1, 0, 0, &echoed(8, b"payload"));
+        assert_eq!(
+            decode_v4(&ipv4(1, 64, 0, 0, &icmp(11, 0, [0; 4], &quoted))),
+            error(Reason::TimeExceeded, b"payload")
+        );
+        let quoted = ipv6(58, &echoed(128, b"payload"));
+        assert_eq!(
+            decode_v6(&icmp(3, 0, [0; 4], &quoted)),
+            error(Reason::TimeExceeded, b"payload")
+        );
+    }
+
+    #[test]
+    fn quoted_options_are_skipped() {
+        let quoted = ipv4(1, 1, 0, 12, &echoed(8, b"payload"));
+        assert_eq!(
+            decode_v4(&ipv4(1, 64, 0, 4, &icmp(11, 0, [0; 4], &quoted))),
+            error(Reason::TimeExceeded, b"payload")
+        );
+    }
+
+    #[test]
+    fn fragmentation_needed_carries_the_mtu() {
+        let quoted = ipv4(1, 64, 0, 0, &echoed(8, b""));
+        let [high, low] = 1400u16.to_be_bytes();
+        assert_eq!(
+            decode_v4(&ipv4(1, 64, 0, 0, &icmp(3, 4, [0, 0, high, low], &quoted))),
+            error(Reason::FragmentationNeeded { mtu: Some(1400) }, b"")
+        );
+        assert_eq!(
+            decode_v4(&ipv4(1, 64, 0, 0, &icmp(3, 4, [0; 4], &quoted))),
+            error(Reason::FragmentationNeeded { mtu: None }, b"")
+        );
+        let quoted = ipv6(58, &echoed(128, b""));
+        assert_eq!(
+            decode_v6(&icmp(2, 0, 1280u32.to_be_bytes(), &quoted)),
+            error(Reason::FragmentationNeeded { mtu: Some(1280) }, b"")
+        );
+        assert_eq!(
+            reason(Version::V4, 3, 4, 1400),
+            Some(Reason::FragmentationNeeded { mtu: Some(1400) })
+        );
+    }
+
+    #[test]
+    fn parameter_problems_carry_the_pointer() {
+        let quoted = ipv4(1, 64, 0, 0, &echoed(8, b""));
+        assert_eq!(
+            decode_v4(&ipv4(1, 64, 0, 0, &icmp(12, 0, [20, 0, 0, 0], &quoted))),
+            error(Reason::ParameterProblem { pointer: Some(20) }, b"")
+        );
+        assert_eq!(
+            decode_v4(&ipv4(1, 64, 0, 0, &icmp(12, 1, [20, 0, 0, 0], &quoted))),
+            error(Reason::ParameterProblem { pointer: None }, b"")
+        );
+        let quoted = ipv6(58, &echoed(128, b""));
+        assert_eq!(
+            decode_v6(&icmp(4, 0, 40u32.to_be_bytes(), &quoted)),
+            error(Reason::ParameterProblem { pointer: Some(40) }, b"")
+        );
+        // The kernel shifts the pointer down itself for the error queue.
+        assert_eq!(
+            reason(Version::V4, 12, 0, 20),
+            Some(Reason::ParameterProblem { pointer: Some(20) })
+        );
+    }
+
+    #[test]
+    fn redirects_carry_the_gateway() {
+        let quoted = ipv4(1, 64, 0, 0, &echoed(8, b""));
+        assert_eq!(
+            decode_v4(&ipv4(1, 64, 0, 0, &icmp(5, 1, [10, 9, 0, 3], &quoted))),
+            error(
+                Reason::Redirect {
+                    gateway: Ipv4Addr::new(10, 9, 0, 3).into()
+                },
+                b""
+            )
+        );
+    }
+
+    #[test]
+    fn unreachable_codes_are_folded() {
+        let quoted = ipv4(1, 64, 0, 0, &echoed(8, b""));
+        assert_eq!(
+            decode_v4(&ipv4(1, 64, 0, 0, &icmp(3, 1, [0; 4], &quoted))),
+            error(Reason::Unreachable(Unreachable::Host), b"")
+        );
+        [
+            (Version::V4, 0, Unreachable::Network),
+            (Version::V4, 12, Unreachable::Host),
+            (Version::V4, 2, Unreachable::Protocol),
+            (Version::V4, 3, Unreachable::Port),
+            (Version::V4, 13, Unreachable::Prohibited),
+            (Version::V4, 5, Unreachable::SourceRouteFailed),
+            (Version::V4, 15, Unreachable::Other { code: 15 }),
+            (Version::V6, 0, Unreachable::Network),
+            (Version::V6, 3, Unreachable::Host),
+            (Version::V6, 4, Unreachable::Port),
+            (Version::V6, 1, Unreachable::Prohibited),
+            (Version::V6, 7, Unreachable::SourceRouteFailed),
+            (Version::V6, 2, Unreachable::Other { code: 2 }),
+        ]
+        .into_iter()
+        .for_each(|(version, code, expected)| assert_eq!(unreachable(version, code), expected));
+    }
+
+    #[test]
+    fn errors_quoting_only_the_header_have_no_payload() {
+        let quoted = ipv4(1, 1, 0, 0, &echoed(8, b"payload")[..8]);
+        assert_eq!(
+            decode_v4(&ipv4(1, 64, 0, 0, &icmp(11, 0, [0; 4], &quoted))),
+            error(Reason::TimeExceeded, b"")
+        );
+        let quoted = ipv4(1, 1, 0, 0, &echoed(8, b"payload")[..7]);
+        assert_eq!(
+            decode_v4(&ipv4(1, 64, 0, 0, &icmp(11, 0, [0; 4], &quoted))),
+            Received::Other
+        );
+    }
+
+    #[test]
+    fn errors_about_other_protocols_are_ignored() {
+        let datagram = [&[0x30, 0x39, 0x00, 0x35, 0, 16, 0, 0][..], b"payload"].concat();
+        let quoted = ipv4(17, 1, 0, 0, &datagram);
+        assert_eq!(
+            decode_v4(&ipv4(1, 64, 0, 0, &icmp(11, 0, [0; 4], &quoted))),
+            Received::Other
+        );
+        let quoted = ipv6(17, &datagram);
+        assert_eq!(decode_v6(&icmp(3, 0, [0; 4], &quoted)), Received::Other);
+    }
+
+    #[test]
+    fn errors_quoting_replies_are_ignored() {
+        let quoted = ipv4(1, 64, 0, 0, &echoed(0, b""));
+        assert_eq!(
+            decode_v4(&ipv4(1, 64, 0, 0, &icmp(11, 0, [0; 4], &quoted))),
+            Received::Other
+        );
+    }
+
+    #[test]
+    fn unknown_types_are_ignored() {
+        let quoted = ipv4(1, 64, 0, 0, &echoed(8, b""));
+        assert_eq!(
+            decode_v4(&ipv4(1, 64, 0, 0, &icmp(13, 0, [0; 4], &quoted))),
+            Received::Other
+        );
+        assert_eq!(reason(Version::V6, 5, 0, 0), None);
+        assert_eq!(
+            reason(Version::V6, 4, 0, 8),
+            Some(Reason::ParameterProblem { pointer: Some(8) })
+        );
+    }
+
+    #[test]
+    fn implausible_stamps_give_way_to_less_precise_ones() {
+        let kernel = Instant::now();
+        let close = kernel + Duration::from_micros(3);
+        let far = kernel + Duration::from_secs(5);
+        assert_eq!(
+            plausible(&[(Clock::Hardware, close), (Clock::Kernel, kernel)], kernel),
+            Some((close, Clock::Hardware))
+        );
+        assert_eq!(
+            plausible(&[(Clock::Hardware, far), (Clock::Kernel, kernel)], kernel),
+            Some((kernel, Clock::Kernel))
+        );
+        // Send stamps are held against the time the request was sent.
+        assert_eq!(plausible(&[(Clock::Kernel, far)], kernel), None);
+    }
+}
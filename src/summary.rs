@@ -0,0 +1,281 @@
+use {
+    crate::{Event, Histogram, Host, Jitter, Reply, Undelivered},
+    std::{
+        fmt::{self, Display, Formatter},
+        net::IpAddr,
+        time::Duration,
+    },
+};
+
+/// Running totals over the events of one destination, printed as the closing
+/// trailer.
+#[derive(Clone, Debug)]
+pub struct Summary {
+    pub host: Host,
+    pub destination: IpAddr,
+    /// The address requests were sent from, where one was chosen.
+    pub source: Option<IpAddr>,
+    /// The interface requests were sent through, where one was chosen.
+    pub interface: Option<String>,
+    pub transmitted: u64,
+    pub received: u64,
+    /// Probes answered with an ICMP error instead of a reply.
+    pub errors: u64,
+    /// Probes the kernel refused to send, which ping counts as transmitted,
+    /// and lost, all the same.
+    pub send_errors: u64,
+    /// Copies of replies and errors for probes answered already, which count
+    /// towards nothing else.
+    pub duplicates: u64,
+    min: Option<Duration>,
+    max: Option<Duration>,
+    /// Sum of the round trip times and of their squares, in microseconds.
+    total: f64,
+    total_squared: f64,
+    histogram: Histogram,
+    variation: Jitter,
+}
+
+/// The percentiles reported alongside min/avg/max/mdev.
+pub const PERCENTILES: [f64; 5] = [50.0, 90.0, 95.0, 99.0, 99.9];
+
+impl Summary {
+    pub fn new(host: Host, destination: IpAddr) -> Self {
+        Self {
+            host,
+            destination,
+            source: None,
+            interface: None,
+            transmitted: 0,
+            received: 0,
+            errors: 0,
+            send_errors: 0,
+            duplicates: 0,
+            min: None,
+            max: None,
+            total: 0.0,
+            total_squared: 0.0,
+            histogram: Histogram::default(),
+            variation: Jitter::default(),
+        }
+    }
+
+    /// Records the address the requests were sent from, for the trailer.
+    pub fn with_source(self, source: IpAddr) -> Self {
+        Self {
+            source: Some(source),
+            ..self
+        }
+    }
+
+    /// Records the interface the requests were sent through, for the
+    /// trailer.
+    pub fn with_interface(self, interface: String) -> Self {
+        Self {
+            interface: Some(interface),
+            ..self
+        }
+    }
+
+    /// Accounts for `event`, unless it is about another destination.
+    pub fn record(&mut self, event: &Event) {
+        if event.destination() != self.destination {
+            return;
+        }
+        match event {
+            Event::Sent { .. } => self.transmitted += 1,
+            Event::Reply(Reply {
+                duplicate: true, ..
+            })
+            | Event::Undelivered(Undelivered {
+                duplicate: true, ..
+            }) => self.duplicates += 1,
+            Event::Reply(reply) => {
+                let micros = reply.round_trip.as_secs_f64() * 1e6;
+                self.received += 1;
+                self.min = self
+                    .min
+                    .min(Some(reply.round_trip))
+                    .or(Some(reply.round_trip));
+                self.max = self.max.max(Some(reply.round_trip));
+                self.total += micros;
+                self.total_squared += micros * micros;
+                self.histogram.record(reply.round_trip);
+                // The pinger tracks the variation across replies already.
+                self.variation = reply.variation;
+            }
+            Event::Timeout(_) => {}
+            Event::Undelivered(_) => self.errors += 1,
+            Event::SendFailure(_) => {
+                self.transmitted += 1;
+                self.send_errors += 1;
+            }
+        }
+    }
+
+    /// Percentage of transmitted requests that were never answered, rounded
+    /// to four decimal places like ping does.
+    pub fn loss(&self) -> f64 {
+        match self.transmitted {
+            0 => 0.0,
+            transmitted => {
+                let loss = transmitted.saturating_sub(self.received) as f64 / transmitted as f64;
+                (loss * 1e6).round() / 1e4
+            }
+        }
+    }
+
+    pub fn min(&self) -> Option<Duration> {
+        self.min
+    }
+
+    pub fn avg(&self) -> Option<Duration> {
+        self.mean().map(|mean| Duration::from_secs_f64(mean / 1e6))
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        self.max
+    }
+
+    /// Standard deviation of the round trip times, what ping calls `mdev`.
+    pub fn mdev(&self) -> Option<Duration> {
+        self.mean().map(|mean| {
+            let variance = self.total_squared / self.received as f64 - mean * mean;
+            Duration::from_secs_f64(variance.max(0.0).sqrt() / 1e6)
+        })
+    }
+
+    /// The round trip time that `percentile` percent of the replies did not
+    /// exceed, to within the resolution of the [`Histogram`]. Bucket bounds
+    /// are pulled in to the fastest and slowest reply, so no percentile lies
+    /// outside of them.
+    pub fn percentile(&self, percentile: f64) -> Option<Duration> {
+        match (self.histogram.percentile(percentile), self.min, self.max) {
+            (Some(rtt), Some(min), Some(max)) => Some(rtt.clamp(min, max)),
+            (rtt, ..) => rtt,
+        }
+    }
+
+    /// Every round trip time seen, in buckets.
+    pub fn histogram(&self) -> &Histogram {
+        &self.histogram
+    }
+
+    /// The RFC 3550 jitter of the replies, with lost probes skipped, as of
+    /// the last reply, and so the same as that reply reports.
+    pub fn jitter(&self) -> Option<Duration> {
+        self.variation.smoothed()
+    }
+
+    /// The mean absolute difference between the round trip times of
+    /// successive replies, with lost probes skipped.
+    pub fn mean_difference(&self) -> Option<Duration> {
+        self.variation.mean_difference()
+    }
+
+    fn mean(&self) -> Option<f64> {
+        match self.received {
+            0 => None,
+            received => Some(self.total / received as f64),
+        }
+    }
+
+    /// Formats the summary in the shape of the per-reply lines:
+    /// `address,summary,transmitted,received,loss,min,avg,max,mdev,errors,send errors,source,interface,p50,p90,p95,p99,p99.9,jitter,mean difference,duplicates`,
+    /// with the round trip times in microseconds and left empty without
+    /// replies, as are the source and interface unless they were chosen.
+    pub fn csv(&self) -> String {
+        let micros = |rtt: Option<Duration>| {
+            rtt.map(|rtt| rtt.as_micros().to_string())
+                .unwrap_or_default()
+        };
+        format!(
+            "{},summary,{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            self.destination,
+            self.transmitted,
+            self.received,
+            self.loss(),
+            micros(self.min()),
+            micros(self.avg()),
+            micros(self.max()),
+            micros(self.mdev()),
+            self.errors,
+            self.send_errors,
+            self.source
+                .map(|source| source.to_string())
+                .unwrap_or_default(),
+            self.interface.as_deref().unwrap_or_default(),
+            PERCENTILES
+                .iter()
+                .map(|percentile| micros(self.percentile(*percentile)))
+                .collect::<Vec<_>>()
+                .join(","),
+            micros(self.jitter()),
+            micros(self.mean_difference()),
+            self.duplicates
+        )
+    }
+}
+
+impl Display for Summary {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+        let millis = |rtt: Option<Duration>| rtt.unwrap_or_default().as_secs_f64() * 1e3;
+        match &self.host {
+            Host::Name(name) => write!(
+                formatter,
+                "--- {} ({}) ping statistics",
+                name, self.destination
+            )?,
+            Host::Address(_) => write!(formatter, "--- {} ping statistics", self.destination)?,
+        }
+        if let Some(source) = self.source {
+            write!(formatter, " from {}", source)?;
+        }
+        if let Some(interface) = &self.interface {
+            write!(formatter, " via {}", interface)?;
+        }
+        writeln!(formatter, " ---")?;
+        write!(
+            formatter,
+            "{} packets transmitted, {} received, ",
+            self.transmitted, self.received
+        )?;
+        if self.duplicates > 0 {
+            write!(formatter, "+{} duplicates, ", self.duplicates)?;
+        }
+        if self.errors > 0 {
+            write!(formatter, "+{} errors, ", self.errors)?;
+        }
+        if self.send_errors > 0 {
+            write!(formatter, "+{} send errors, ", self.send_errors)?;
+        }
+        write!(formatter, "{}% packet loss", self.loss())?;
+        match self.received {
+            0 => Ok(()),
+            _ => {
+                let (labels, percentiles): (Vec<_>, Vec<_>) = PERCENTILES
+                    .iter()
+                    .map(|percentile| {
+                        (
+                            format!("p{}", percentile),
+                            format!("{:.3}", millis(self.percentile(*percentile))),
+                        )
+                    })
+                    .unzip();
+                write!(
+                    formatter,
+                    "\nrtt min/avg/max/mdev = {:.3}/{:.3}/{:.3}/{:.3} ms\nrtt {} = {} ms\n\
+                     jitter = {:.3} ms, mean successive difference = {:.3} ms",
+                    millis(self.min()),
+                    millis(self.avg()),
+                    millis(self.max()),
+                    millis(self.mdev()),
+                    labels.join("/"),
+                    percentiles.join("/"),
+                    millis(self.jitter()),
+                    millis(self.mean_difference())
+                )
+            }
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use {
+    crate::{payload::byte, Error},
+    derive_more::{From, Into},
+    std::{
+        fmt::{self, Display, Formatter},
+        str::FromStr,
+    },
+};
+
+/// The IPv4 type of service or IPv6 traffic class byte: a DSCP code point in
+/// the upper six bits and ECN in the lower two.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, From, Into)]
+pub struct Tos(u8);
+
+impl Tos {
+    /// The differentiated services code point.
+    pub fn dscp(self) -> u8 {
+        self.0 >> 2
+    }
+
+    fn name(self) -> Option<String> {
+        match (self.dscp(), self.0 & 0b11) {
+            (46, 0) => Some("EF".to_string()),
+            (44, 0) => Some("VA".to_string()),
+            (1, 0) => Some("LE".to_string()),
+            (dscp, 0) if dscp % 8 == 0 => Some(format!("CS{}", dscp / 8)),
+            (dscp, 0) if (1..=4).contains(&(dscp / 8)) && [2, 4, 6].contains(&(dscp % 8)) => {
+                Some(format!("AF{}{}", dscp / 8, dscp % 8 / 2))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Parses a DSCP name (`EF`, `AF41`, `CS1`, `VA`, `LE`), or a whole TOS byte
+/// in decimal or `0x` hexadecimal.
+impl FromStr for Tos {
+    type Err = Error;
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let name = text.to_ascii_uppercase();
+        let digit = |index: usize| {
+            name.get(index..index + 1)
+                .and_then(|digit| u8::from_str(digit).ok())
+        };
+        let dscp = match (name.as_str(), name.len()) {
+            ("EF", _) => Some(46),
+            ("VA", _) => Some(44),
+            ("LE", _) => Some(1),
+            (cs, 3) if cs.starts_with("CS") => {
+                digit(2).filter(|class| *class <= 7).map(|class| class * 8)
+            }
+            (af, 4) if af.starts_with("AF") => digit(2)
+                .filter(|class| (1..=4).contains(class))
+                .zip(digit(3).filter(|drop| (1..=3).contains(drop)))
+                .map(|(class, drop)| class * 8 + drop * 2),
+            _ => None,
+        };
+        let (digits, radix) = text.strip_prefix("0x").map_or((text, 10), |hex| (hex, 16));
+        match dscp {
+            Some(dscp) => Ok(Self(dscp << 2)),
+            None => byte(digits, radix).map(Self).ok_or_else(|| {
+                format!(
+                    "unknown TOS {}, expected a DSCP name such as EF, AF41 or CS1, or a byte",
+                    text
+                )
+                .into()
+            }),
+        }
+    }
+}
+
+/// Formats the byte as its DSCP name where it has one, and in hexadecimal
+/// otherwise.
+impl Display for Tos {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => write!(formatter, "{}", name),
+            None => write!(formatter, "{:#04x}", self.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_round_trip() {
+        let names = ["EF", "VA", "LE"]
+            .into_iter()
+            .map(str::to_string)
+            .chain((0..=7).map(|class| format!("CS{}", class)))
+            .chain(
+                (1..=4).flat_map(|class| (1..=3).map(move |drop| format!("AF{}{}", class, drop))),
+            );
+        names.for_each(|name| {
+            let tos = name.parse::<Tos>().unwrap();
+            assert_eq!(tos.to_string(), name);
+            assert_eq!(name.to_ascii_lowercase().parse::<Tos>().unwrap(), tos);
+        });
+    }
+
+    #[test]
+    fn names_have_their_code_points() {
+        let dscp = |name: &str| name.parse::<Tos>().unwrap().dscp();
+        assert_eq!(dscp("EF"), 46);
+        assert_eq!(dscp("VA"), 44);
+        assert_eq!(dscp("LE"), 1);
+        assert_eq!(dscp("CS0"), 0);
+        assert_eq!(dscp("CS6"), 48);
+        assert_eq!(dscp("AF11"), 10);
+        assert_eq!(dscp("AF23"), 22);
+        assert_eq!(dscp("AF41"), 34);
+        assert_eq!(dscp("AF43"), 38);
+    }
+
+    #[test]
+    fn bytes_parse() {
+        let byte = |text: &str| u8::from(text.parse::<Tos>().unwrap());
+        assert_eq!(byte("0xb8"), 0xb8);
+        assert_eq!(byte("0x01"), 1);
+        assert_eq!(byte("184"), 184);
+        assert_eq!(byte("0"), 0);
+        assert_eq!(byte("255"), 255);
+        assert_eq!("184".parse::<Tos>().unwrap().to_string(), "EF");
+        assert_eq!("0x01".parse::<Tos>().unwrap().to_string(), "0x01");
+        assert_eq!("0xb9".parse::<Tos>().unwrap().to_string(), "0xb9");
+    }
+
+    #[test]
+    fn unknown_names_and_bytes_are_refused() {
+        [
+            "AF51", "AF10", "AF14", "AF0", "AF111", "CS8", "CS", "CS10", "E", "256", "-1", "+1",
+            "0x", "0x100", "0x+1", "0xzz", "",
+        ]
+        .into_iter()
+        .for_each(|text| assert!(text.parse::<Tos>().is_err(), "{}", text));
+    }
+
+    #[test]
+    fn refusals_say_what_is_expected() {
+        let error = "XX".parse::<Tos>().unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "unknown TOS XX, expected a DSCP name such as EF, AF41 or CS1, or a byte"
+        );
+    }
+}
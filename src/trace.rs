@@ -0,0 +1,216 @@
+use {
+    crate::{
+        rounds::Rounds, Arg, Error, Event, Pinger, Reason, Reply, Resolver, Undelivered,
+        Unreachable,
+    },
+    futures_util::{
+        future::ready,
+        stream::{try_unfold, Stream},
+        TryStreamExt,
+    },
+    std::{
+        fmt::{self, Display, Formatter},
+        net::IpAddr,
+        time::{Duration, SystemTime},
+    },
+};
+
+const DEFAULT_MAX_HOPS: u8 = 30;
+
+/// Every probe of a hop has a sequence number of its own, and they are 16 bits.
+const MAX_PROBES: u64 = u16::MAX as u64 + 1;
+
+/// An answer to one of the probes sent to a hop.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HopReply {
+    pub responder: IpAddr,
+    pub round_trip: Duration,
+    /// What the responder could not reach, if it answered with a
+    /// Destination Unreachable error rather than a time exceeded one or a
+    /// reply.
+    pub unreachable: Option<Unreachable>,
+}
+
+/// The probes sent with one time to live, and what answered them.
+#[derive(Clone, Debug)]
+pub struct Hop {
+    pub destination: IpAddr,
+    /// The time to live or hop limit the probes were sent with.
+    pub hop: u8,
+    /// One entry per probe, in sequence order, with `None` for those that
+    /// went unanswered.
+    pub probes: Vec<Option<HopReply>>,
+    /// Whether the destination itself replied, which ends the trace, as does
+    /// any probe coming back unreachable.
+    pub reached: bool,
+    /// Wall clock time at which the last probe was settled.
+    pub timestamp: SystemTime,
+}
+
+impl Hop {
+    /// Every address that answered, in the order they first did.
+    pub fn responders(&self) -> Vec<IpAddr> {
+        self.probes
+            .iter()
+            .flatten()
+            .fold(Vec::new(), |mut responders, reply| {
+                if !responders.contains(&reply.responder) {
+                    responders.push(reply.responder);
+                }
+                responders
+            })
+    }
+
+    /// Whether any probe came back unreachable.
+    pub fn unreachable(&self) -> bool {
+        self.probes
+            .iter()
+            .flatten()
+            .any(|reply| reply.unreachable.is_some())
+    }
+}
+
+/// Formats the hop as an `address,hop,responders,microseconds...` line, with
+/// several responders separated by `|`, a `*` for every probe that went
+/// unanswered and what could not be reached after the time of a probe that
+/// came back unreachable, e.g. `10.9.1.2,1,10.9.0.2,87,92,*` or
+/// `10.9.1.9,2,10.9.1.1,3071 host unreachable`.
+impl Display for Hop {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+        let responders = self
+            .responders()
+            .iter()
+            .map(IpAddr::to_string)
+            .collect::<Vec<_>>()
+            .join("|");
+        write!(
+            formatter,
+            "{},{},{}",
+            self.destination, self.hop, responders
+        )?;
+        self.probes.iter().try_for_each(|probe| match probe {
+            Some(HopReply {
+                round_trip,
+                unreachable: Some(unreachable),
+                ..
+            }) => write!(formatter, ",{} {}", round_trip.as_micros(), unreachable),
+            Some(reply) => write!(formatter, ",{}", reply.round_trip.as_micros()),
+            None => write!(formatter, ",*"),
+        })
+    }
+}
+
+/// Traces the route to a destination by sending its echo requests with an
+/// increasing time to live, one hop after the other, until the destination
+/// replies, a router or host answers that it is unreachable, or the hop limit
+/// is reached.
+///
+/// A trace is made from a [`Pinger`] with a single destination and a number
+/// of requests, and probes every hop with those requests at their interval,
+/// over the pinger's sockets. Routers along the way answer with time exceeded
+/// errors.
+pub struct Trace {
+    rounds: Rounds,
+    max_hops: u8,
+}
+
+impl TryFrom<Pinger> for Trace {
+    type Error = Error;
+    fn try_from(pinger: Pinger) -> Result<Self, Self::Error> {
+        let rounds = Rounds::new(pinger, "hop")?;
+        match rounds.requests <= MAX_PROBES {
+            true => Ok(Self {
+                rounds,
+                max_hops: DEFAULT_MAX_HOPS,
+            }),
+            false => Err(format!("a trace sends at most {} requests per hop", MAX_PROBES).into()),
+        }
+    }
+}
+
+impl<'a> TryFrom<(Arg, &'a Resolver)> for Trace {
+    type Error = Error;
+    fn try_from(arg: (Arg, &'a Resolver)) -> Result<Self, Self::Error> {
+        Pinger::try_from(arg)?.try_into()
+    }
+}
+
+impl Trace {
+    /// Overrides how many hops to try before giving up, 30 by default.
+    pub fn with_max_hops(self, max_hops: u8) -> Self {
+        Self { max_hops, ..self }
+    }
+
+    /// Runs the trace, yielding every hop as soon as its probes are settled.
+    pub fn events(self) -> impl Stream<Item = Result<Hop, Error>> {
+        try_unfold((self, Some(1)), |(mut trace, hop)| async move {
+            let hop = match hop.filter(|hop| *hop <= trace.max_hops) {
+                Some(hop) => trace.hop(hop).await?,
+                None => return Ok(None),
+            };
+            let next = match hop.reached || hop.unreachable() {
+                true => None,
+                false => hop.hop.checked_add(1),
+            };
+            Ok(Some((hop, (trace, next))))
+        })
+    }
+
+    async fn hop(&mut self, hop: u8) -> Result<Hop, Error> {
+        let unanswered = vec![None; self.rounds.requests as usize];
+        let (probes, reached) = self
+            .rounds
+            .round(|_, marking| marking.ttl = Some(hop))
+            .try_fold((unanswered, false), |(mut probes, reached), event| {
+                let (sequence, reply, reached) = match event {
+                    Event::Reply(Reply {
+                        sequence,
+                        responder,
+                        round_trip,
+                        duplicate: false,
+                        ..
+                    }) => (
+                        sequence,
+                        HopReply {
+                            responder,
+                            round_trip,
+                            unreachable: None,
+                        },
+                        true,
+                    ),
+                    Event::Undelivered(Undelivered {
+                        sequence,
+                        responder,
+                        round_trip,
+                        reason: reason @ (Reason::TimeExceeded | Reason::Unreachable(_)),
+                        duplicate: false,
+                        ..
+                    }) => (
+                        sequence,
+                        HopReply {
+                            responder,
+                            round_trip,
+                            unreachable: match reason {
+                                Reason::Unreachable(unreachable) => Some(unreachable),
+                                _ => None,
+                            },
+                        },
+                        reached,
+                    ),
+                    _ => return ready(Ok((probes, reached))),
+                };
+                if let Some(probe) = probes.get_mut(usize::from(sequence)) {
+                    *probe = Some(reply);
+                }
+                ready(Ok((probes, reached)))
+            })
+            .await?;
+        Ok(Hop {
+            destination: self.rounds.destination,
+            hop,
+            probes,
+            reached,
+            timestamp: SystemTime::now(),
+        })
+    }
+}